@@ -1,14 +1,23 @@
+// Same bindings as the SFML version
+#[path = "../../rust-doomlike/src/input.rs"]
+mod input;
+#[path = "../../rust-doomlike/src/json.rs"]
+mod json;
 mod renderer;
+use crate::input::{Action, Bindings, Input};
 use crate::renderer::Renderer;
 use crate::renderer::player::Player;
 
 use sfml::{
     // audio::{Sound, SoundBuffer, SoundSource},
-    system::{Vector2f},
-    window::{ContextSettings, Event, Key, Style},
+    system::{Vector2f, Vector2i, Clock},
+    window::{ContextSettings, Event, Style},
     graphics::{Color, RenderTarget, RenderWindow, View},
 };
 
+use std::path::Path;
+use std::process::exit;
+
 
 const WIDTH: u32 = 640;
 const HEIGHT: u32 = 480;
@@ -17,10 +26,21 @@ const RENDER_W: f32 = 160.0;
 const RENDER_H: f32 = 120.0;
 
 const FPS: u32 = 20;
-const VELOCITY: i32 = 4;
+
+// Simulation runs in fixed steps, frames are drawn between them
+const TICK: f32 = 1.0 / 20.0;
+const MAX_LAG: f32 = 0.25;
+
+// Key bindings and mouse settings, defaults are used if the file doesn't exist
+const BINDINGS: &str = "bindings.json";
 
 
 fn main() {
+    let bindings = Bindings::bindings_loader(Path::new(BINDINGS)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+
     let context_settings = ContextSettings {..Default::default()};
     let mut window = RenderWindow::new(
         (WIDTH, HEIGHT),
@@ -37,61 +57,68 @@ fn main() {
     let mut player = Player::new();
     let mut renderer = Renderer::new();
 
-    let (mut up, mut right, mut down, mut left, mut strafe_left, mut strafe_right, mut move_up, mut move_down, mut look_up, mut look_down) 
-      = (false, false, false, false, false, false, false, false, false, false);
+    let mut input = Input::new(bindings);
 
+    // Mouse look grabs the cursor
+    let center = Vector2i::new(WIDTH as i32 / 2, HEIGHT as i32 / 2);
+    let mut focused = true;
+    let mut mouse_rest: (f32, f32) = (0.0, 0.0);
+    if input.mouse_look() {
+        window.set_mouse_cursor_grabbed(true);
+        window.set_mouse_cursor_visible(false);
+        window.set_mouse_position(center);
+    }
 
+    let mut previous = player.clone();
+    let mut lag: f32 = 0.0;
+
+    let mut clock = Clock::start();
     loop {
+        let delta_time = clock.elapsed_time().as_seconds();
+        clock.restart();
+
         while let Some(event) = window.poll_event() {
             match event {
-                Event::Closed | Event::KeyPressed {code: Key::Escape, ..} => return,
-
-                Event::KeyPressed  {code: Key::W, ..} => up = true,
-                Event::KeyReleased {code: Key::W, ..} => up = false,
-
-                Event::KeyPressed  {code: Key::S, ..} => down = true,
-                Event::KeyReleased {code: Key::S, ..} => down = false,
+                Event::Closed => return,
 
-                Event::KeyPressed  {code: Key::D, ..} => right = true,
-                Event::KeyReleased {code: Key::D, ..} => right = false,
-
-                Event::KeyPressed  {code: Key::A, ..} => left = true,
-                Event::KeyReleased {code: Key::A, ..} => left = false,
-
-                Event::KeyPressed  {code: Key::Left, ..}  => strafe_left = true,
-                Event::KeyReleased {code: Key::Left, ..}  => strafe_left = false,
-
-                Event::KeyPressed  {code: Key::Right, ..} => strafe_right = true,
-                Event::KeyReleased {code: Key::Right, ..} => strafe_right = false,
-
-                Event::KeyPressed  {code: Key::Q, ..}  => move_up = true,
-                Event::KeyReleased {code: Key::Q, ..}  => move_up = false,
-
-                Event::KeyPressed  {code: Key::E, ..}  => move_down = true,
-                Event::KeyReleased {code: Key::E, ..}  => move_down = false,
-
-                Event::KeyPressed  {code: Key::Up, ..} => look_up = true,
-                Event::KeyReleased {code: Key::Up, ..} => look_up = false,
-
-                Event::KeyPressed  {code: Key::Down, ..} => look_down = true,
-                Event::KeyReleased {code: Key::Down, ..} => look_down = false,
+                Event::LostFocus   => {input.release_all(); focused = false;},
+                Event::GainedFocus => {focused = true; window.set_mouse_position(center);},
 
                 _ => {}
             }
+            input.handle(&event);
         } window.clear(Color::rgb(80, 100, 80));
 
-        if up    {player.advance(Key::W, VELOCITY);}
-        if down  {player.advance(Key::S, VELOCITY);}
-        if right {player.advance(Key::D, VELOCITY);}
-        if left  {player.advance(Key::A, VELOCITY);}
-        if strafe_left  {player.advance(Key::Left,  VELOCITY);}
-        if strafe_right {player.advance(Key::Right, VELOCITY);}
-        if move_up   {player.advance(Key::Q, VELOCITY);}
-        if move_down {player.advance(Key::E, VELOCITY);}
-        if look_up   {player.advance(Key::Up,   VELOCITY);}
-        if look_down {player.advance(Key::Down, VELOCITY);} 
-
-        renderer.draw(&player, &mut window);
+        if input.triggered(Action::Quit) {return;}
+
+        // Relative mouse look, cursor goes back to the middle every frame
+        if focused && input.mouse_look() {
+            let moved = window.mouse_position() - center;
+            window.set_mouse_position(center);
+
+            // Angles go the other way round here and the player turns in whole degrees,
+            // the rest waits for the next mouse movement
+            let (yaw, pitch) = input.mouse(moved.x, moved.y);
+            let (angle, look_up_down) = (mouse_rest.0 - yaw, mouse_rest.1 - pitch);
+            mouse_rest = (angle.fract(), look_up_down.fract());
+
+            let (angle, look_up_down) = (angle.trunc() as i32, look_up_down.trunc() as i32);
+            player.look(angle, look_up_down);
+            previous.look(angle, look_up_down);
+        }
+
+        // Movement doesn't depend on the frame rate, long stalls don't make up for all missed ticks
+        lag += delta_time.min(MAX_LAG);
+        while lag >= TICK {
+            previous = player.clone();
+            for action in Action::MOVES {
+                let amount = input.value(action);
+                if amount > 0.0 {player.advance(action, TICK * amount);}
+            }
+            lag -= TICK;
+        }
+
+        renderer.draw(&player.interpolate(&previous, lag / TICK), &mut window);
         window.display();
     }
 }
@@ -309,6 +309,10 @@ impl Renderer {
         let width  = 160;
         let height = 120;
 
+        // Looking up and down moves the horizon by the same amount for every wall,
+        // so the floor and ceiling fills between walls stay level with them
+        let horizon = height/2 + p.look_up_down*200/32;
+
         let mut wx: [i32; 4] = [0; 4];
         let mut wy: [i32; 4] = [0; 4];
         let mut wz: [i32; 4] = [0; 4];
@@ -367,8 +371,8 @@ impl Renderer {
     
     
                     // World Z height
-                    wz[0] = self.sectors[s as usize].z1 - p.pos.z + ((p.look_up_down * wy[0])/32);
-                    wz[1] = self.sectors[s as usize].z1 - p.pos.z + ((p.look_up_down * wy[1])/32);
+                    wz[0] = self.sectors[s as usize].z1 - p.pos.z;
+                    wz[1] = self.sectors[s as usize].z1 - p.pos.z;
                     wz[2] = (wz[0] + self.sectors[s as usize].z2) - self.sectors[s as usize].z1;
                     wz[3] = (wz[1] + self.sectors[s as usize].z2) - self.sectors[s as usize].z1;
             
@@ -389,11 +393,11 @@ impl Renderer {
                         Self::clip_behind(&mut wx[3], &mut wy[3], &mut wz[3],  x2, y2, z2);
                     }
             
-                    // Screen X and Y position
-                    wx[0] = wx[0]*200 / wy[0]+width/2;  wy[0] = wz[0]*200 / wy[0]+height/2;
-                    wx[1] = wx[1]*200 / wy[1]+width/2;  wy[1] = wz[1]*200 / wy[1]+height/2;
-                    wx[2] = wx[2]*200 / wy[2]+width/2;  wy[2] = wz[2]*200 / wy[2]+height/2;
-                    wx[3] = wx[3]*200 / wy[3]+width/2;  wy[3] = wz[3]*200 / wy[3]+height/2;
+                    // Screen X and Y position, Y counts from the horizon
+                    wx[0] = wx[0]*200 / wy[0]+width/2;  wy[0] = wz[0]*200 / wy[0]+horizon;
+                    wx[1] = wx[1]*200 / wy[1]+width/2;  wy[1] = wz[1]*200 / wy[1]+horizon;
+                    wx[2] = wx[2]*200 / wy[2]+width/2;  wy[2] = wz[2]*200 / wy[2]+horizon;
+                    wx[3] = wx[3]*200 / wy[3]+width/2;  wy[3] = wz[3]*200 / wy[3]+horizon;
             
                     
                     let (x1, x2,  y1, y2, y3, y4) = (wx[0], wx[1],  wy[0], wy[1],  wy[2], wy[3]);
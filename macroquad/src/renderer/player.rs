@@ -1,9 +1,21 @@
 use sfml::{
     system::{Vector3i},
-    window::{Key}
 };
 
+use crate::input::Action;
 
+
+// Per second
+const MOVE_SPEED: f32 = 80.0;       // Walking and strafing
+const FLY_SPEED: f32 = 80.0;        // Up and down with Q/E
+const TURN_SPEED: f32 = 80.0;       // Degrees
+const LOOK_SPEED: f32 = 20.0;       // Looking up and down
+
+// Renderer moves the horizon instead of turning the camera, which looks wrong much further than this
+const MAX_LOOK: i32 = 16;
+
+
+#[derive(Clone)]
 pub struct Player {
     pub pos: Vector3i,
     pub angle: i32,
@@ -34,43 +46,47 @@ impl Player {
     }
 
 
-    pub fn advance(&mut self, key: Key, velocity: i32) -> () {
+    // Do `action` for `time` seconds, called once per simulation tick
+    pub fn advance(&mut self, action: Action, time: f32) -> () {
         // I can do `self.angle as usize` as well but I'm trying to be carefull
-        let delta_x = (self.sin[usize::try_from(self.angle).unwrap()] * velocity as f32) as i32;
-        let delta_y = (self.cos[usize::try_from(self.angle).unwrap()] * velocity as f32) as i32;
+        let delta_x = (self.sin[usize::try_from(self.angle).unwrap()] * MOVE_SPEED * time) as i32;
+        let delta_y = (self.cos[usize::try_from(self.angle).unwrap()] * MOVE_SPEED * time) as i32;
+        let turn = f32::round(TURN_SPEED * time) as i32;
+        let fly  = f32::round(FLY_SPEED  * time) as i32;
+        let look = f32::round(LOOK_SPEED * time) as i32;
 
-        match key {
+        match action {
             // Move forward/backward and rotate
-            Key::W => {
-                self.pos.x = self.pos.x + delta_x as i32;
-                self.pos.y = self.pos.y + delta_y as i32;
+            Action::MoveForward  => {
+                self.pos.x += delta_x;
+                self.pos.y += delta_y;
             },
-            Key::S => {
-                self.pos.x = self.pos.x - delta_x as i32;
-                self.pos.y = self.pos.y - delta_y as i32;
+            Action::MoveBackward => {
+                self.pos.x -= delta_x;
+                self.pos.y -= delta_y;
             },
-            Key::D => {
-                self.angle = self.angle + 4;
-                if self.angle > 359 {self.angle = self.angle - 359;}
+            Action::TurnRight    => {
+                self.angle += turn;
+                if self.angle > 359 {self.angle -= 360;}
             },
-            Key::A => {
-                self.angle = self.angle - 4;
-                if self.angle <   0 {self.angle = self.angle + 359;}
+            Action::TurnLeft     => {
+                self.angle -= turn;
+                if self.angle <   0 {self.angle += 360;}
             },
 
             // Move up and down in Z axis
-            Key::Q  => self.pos.z = self.pos.z - 4,
-            Key::E  => self.pos.z = self.pos.z + 4,
+            Action::FlyUp   => self.pos.z -= fly,
+            Action::FlyDown => self.pos.z += fly,
 
-            Key::Up   => self.look_up_down = self.look_up_down + 1,
-            Key::Down => self.look_up_down = self.look_up_down - 1,
+            Action::LookUp   => self.look_up_down = (self.look_up_down + look).min(MAX_LOOK),
+            Action::LookDown => self.look_up_down = (self.look_up_down - look).max(-MAX_LOOK),
             
             // Strafe right/left
-            Key::Right => {
+            Action::StrafeRight => {
                 self.pos.x = self.pos.x + delta_y as i32;
                 self.pos.y = self.pos.y - delta_x as i32;
             },
-            Key::Left  => {
+            Action::StrafeLeft  => {
                 self.pos.x = self.pos.x - delta_y as i32;
                 self.pos.y = self.pos.y + delta_x as i32;
             },
@@ -78,4 +94,24 @@ impl Player {
             _ => return
         }
     }
-}
\ No newline at end of file
+
+    // Mouse look, both in whole degrees
+    pub fn look(&mut self, angle: i32, look_up_down: i32) {
+        self.angle = (self.angle + angle).rem_euclid(360);
+        self.look_up_down = (self.look_up_down + look_up_down).clamp(-MAX_LOOK, MAX_LOOK);
+    }
+
+    // Pose to draw between the previous tick and this one, `alpha` goes from 0 (previous) to 1 (this one)
+    pub fn interpolate(&self, previous: &Player, alpha: f32) -> Player {
+        let lerp = |a: i32, b: i32| a + f32::round((b - a) as f32 * alpha) as i32;
+
+        // Shorter way around, e.g. 350 to 10 goes over 0
+        let turn = (self.angle - previous.angle + 540) % 360 - 180;
+
+        let mut pose = self.clone();
+        pose.pos = Vector3i::new(lerp(previous.pos.x, self.pos.x), lerp(previous.pos.y, self.pos.y), lerp(previous.pos.z, self.pos.z));
+        pose.angle = (previous.angle + f32::round(turn as f32 * alpha) as i32).rem_euclid(360);
+        pose.look_up_down = lerp(previous.look_up_down, self.look_up_down);
+        pose
+    }
+}
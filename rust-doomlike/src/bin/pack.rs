@@ -0,0 +1,81 @@
+// Packs data directories into a single resource pack the game can load with `--pack`.
+//
+//     cargo run --bin pack -- data.pak src
+//
+// Only `levels/`, `textures/`, `palettes/` and `fonts/` are packed, lumps are named
+// by their path relative to the data directory (e.g. "textures/T0.bmp").
+// With more than one data directory later ones override files of earlier ones.
+#[path = "../resources/archive.rs"]
+#[allow(dead_code)]     // Reading is for the game
+mod archive;
+use archive::Archive;
+
+use clap::Parser;
+
+use std::collections::BTreeMap;
+use std::fs::{read, read_dir};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+
+const LUMP_DIRS: [&str; 4] = ["levels", "textures", "palettes", "fonts"];
+
+#[derive(Parser, Debug)]
+#[command(name = "pack", about = "Packs rust-doomlike data directories into a resource pack")]
+struct Args {
+    /// Resource pack to create
+    output: PathBuf,
+
+    /// Data directories to pack
+    #[arg(required = true)]
+    data: Vec<PathBuf>,
+}
+
+
+fn collect(dir: &Path, name: &str, lumps: &mut BTreeMap<String, PathBuf>) -> io::Result<()> {
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let lump = [name, "/", &path.file_name().unwrap().to_string_lossy()].join("");
+
+        if path.is_dir() {collect(&path, &lump, lumps)?;}
+        else {lumps.insert(lump, path);}
+    } Ok(())
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let mut lumps: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for data in args.data.iter() {
+        for dir in LUMP_DIRS {
+            let path = data.join(dir);
+            if !path.is_dir() {continue;}
+
+            if let Err(e) = collect(&path, dir, &mut lumps) {
+                eprintln!("{}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut packed: Vec<(String, Vec<u8>)> = Vec::new();
+    for (lump, path) in lumps {
+        match read(&path) {
+            Ok(data) => {
+                println!("{:>9}  {}", data.len(), lump);
+                packed.push((lump, data));
+            },
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Err(e) = Archive::write(&args.output, &packed) {
+        eprintln!("{}: {}", args.output.display(), e);
+        process::exit(1);
+    }
+    println!("{} lumps packed into {}", packed.len(), args.output.display());
+}
@@ -0,0 +1,101 @@
+use clap::Parser;
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+
+// Everything that used to be hardcoded in main.rs
+#[derive(Parser, Debug)]
+#[command(name = "rust-doomlike", about = "Doom-like sector renderer")]
+pub struct Config {
+    /// Directory with levels, textures and fonts
+    #[arg(short, long, default_value = "src")]
+    pub data: PathBuf,
+
+    /// Resource pack stacked on top of the data directory, can be given more than once (last one wins)
+    #[arg(short, long)]
+    pub pack: Vec<PathBuf>,
+
+    /// Level file to load. A relative path is tried from the working directory first,
+    /// then from the data directory and packs
+    #[arg(short, long, default_value = "levels/level.lvl")]
+    pub level: PathBuf,
+
+    /// Directory with textures, looked up like the level
+    #[arg(short, long, default_value = "textures")]
+    pub textures: PathBuf,
+
+    /// Key bindings and mouse settings, defaults are used if the file doesn't exist
+    #[arg(short, long, default_value = "bindings.json")]
+    pub bindings: PathBuf,
+
+    /// Window width
+    #[arg(long, default_value_t = 640)]
+    pub width: u32,
+
+    /// Window height
+    #[arg(long, default_value_t = 480)]
+    pub height: u32,
+
+    /// Internal render width, scaled up to the window
+    #[arg(long, default_value_t = 160)]
+    pub render_width: u32,
+
+    /// Internal render height, scaled up to the window
+    #[arg(long, default_value_t = 120)]
+    pub render_height: u32,
+
+    /// Field of view, as focal length in pixels of 160 pixels wide frame
+    #[arg(long, default_value_t = 200)]
+    pub fov: i32,
+
+    /// Player radius used for collision with walls
+    #[arg(long, default_value_t = 8.0)]
+    pub radius: f32,
+
+    /// Frame rate limit
+    #[arg(long, default_value_t = 30)]
+    pub fps: u32,
+
+    /// Check the level and textures, print all problems and exit
+    #[arg(long)]
+    pub check: bool,
+}
+
+impl Config {
+    pub fn load() -> Self {
+        let mut config = Config::parse();
+        config.data = resolve(&config.data);
+        config.pack = config.pack.iter().map(|p| resolve(p)).collect();
+        config.bindings = resolve(&config.bindings);
+        config.level = from_working_dir(&config.level);
+        config.textures = from_working_dir(&config.textures);
+        config
+    }
+}
+
+
+// Relative paths are tried from the working directory first,
+// then from the directory of the executable and all of its parents,
+// so the game can be started from anywhere (e.g. `target/debug/`).
+// If nothing is found, path is returned as is and loader will complain about it.
+pub fn resolve(path: &Path) -> PathBuf {
+    if path.is_absolute() || path.exists() {return path.to_path_buf();}
+
+    if let Ok(exe) = env::current_exe() {
+        for dir in exe.ancestors().skip(1) {
+            let candidate = dir.join(path);
+            if candidate.exists() {return candidate;}
+        }
+    }
+
+    path.to_path_buf()
+}
+
+// Level and textures given on the command line (`-l mymaps/x.lvl`) are taken from the working directory
+// if they're there. They're made absolute, so joining them to the data directory gives the same path back.
+// Anything else stays relative and is looked up in the data directory and packs.
+fn from_working_dir(path: &Path) -> PathBuf {
+    if path.is_absolute() || !path.exists() {return path.to_path_buf();}
+    env::current_dir().map_or(path.to_path_buf(), |dir| dir.join(path))
+}
@@ -0,0 +1,297 @@
+// The macroquad version includes this file too, so it only uses json.rs from the rest of the crate
+use serde::Deserialize;
+use sfml::window::{joystick, Event, Key};
+use thiserror::Error;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::read;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::json::json_reason;
+
+
+#[derive(Debug, Error)]
+pub enum BindingsError {
+    #[error("{}: {source}", path.display())]
+    Io {path: PathBuf, source: io::Error},
+
+    // Bad syntax, unknown key or action names etc.
+    #[error("{}:{line}:{column}: {reason}", path.display())]
+    Json {path: PathBuf, line: usize, column: usize, reason: String},
+}
+
+
+// Everything player can do, keys are bound to these and the rest of the game only sees actions
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    StrafeLeft,
+    StrafeRight,
+    TurnLeft,
+    TurnRight,
+    LookUp,
+    LookDown,
+    FlyUp,
+    FlyDown,
+    Use,            // Doors, lifts and switches
+
+    Reload,
+    DepthView,      // Show depth buffer instead of the picture
+    Quit,
+}
+impl Action {
+    // Actions that move the player every tick while held
+    pub const MOVES: [Action; 10] = [
+        Action::MoveForward, Action::MoveBackward, Action::StrafeLeft, Action::StrafeRight,
+        Action::TurnLeft, Action::TurnRight, Action::LookUp, Action::LookDown,
+        Action::FlyUp, Action::FlyDown,
+    ];
+}
+
+
+// Joystick axes, the same as SFML's which can't be read from JSON.
+// Sticks are usually X/Y and U/V (or Z/R), triggers Z and R, the D-pad PovX/PovY.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Axis {X, Y, Z, R, U, V, PovX, PovY}
+impl From<joystick::Axis> for Axis {
+    fn from(axis: joystick::Axis) -> Self {
+        match axis {
+            joystick::Axis::X => Axis::X,
+            joystick::Axis::Y => Axis::Y,
+            joystick::Axis::Z => Axis::Z,
+            joystick::Axis::R => Axis::R,
+            joystick::Axis::U => Axis::U,
+            joystick::Axis::V => Axis::V,
+            joystick::Axis::PovX => Axis::PovX,
+            joystick::Axis::PovY => Axis::PovY,
+        }
+    }
+}
+
+
+// Missing fields keep their defaults, so the file only needs what's different
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Bindings {
+    // SFML key name (e.g. "W", "LShift", "Num1") to action
+    pub keys: HashMap<Key, Action>,
+
+    // Joystick axis to actions at its negative and positive end, e.g. "X": ["StrafeLeft", "StrafeRight"].
+    // Any gamepad works, it's only about which axis moves which way.
+    pub axes: HashMap<Axis, [Action; 2]>,
+    // Part of every axis around the middle that does nothing, 0 - 1. Sticks don't rest exactly at 0.
+    pub dead_zone: f32,
+
+    // Degrees per pixel of mouse movement, 0 turns mouse look off
+    pub mouse_sensitivity: f32,
+    pub invert_mouse: bool,
+}
+impl Default for Bindings {
+    fn default() -> Self {
+        let keys = [
+            (Key::W, Action::MoveForward),
+            (Key::S, Action::MoveBackward),
+            (Key::Left, Action::StrafeLeft),
+            (Key::Right, Action::StrafeRight),
+            (Key::A, Action::TurnLeft),
+            (Key::D, Action::TurnRight),
+            (Key::Up, Action::LookUp),
+            (Key::Down, Action::LookDown),
+            (Key::Q, Action::FlyUp),
+            (Key::E, Action::FlyDown),
+            (Key::Space, Action::Use),
+            (Key::Enter, Action::Reload),
+            (Key::F3, Action::DepthView),
+            (Key::Escape, Action::Quit),
+        ];
+
+        let axes = [
+            (Axis::X, [Action::StrafeLeft, Action::StrafeRight]),
+            (Axis::Y, [Action::MoveForward, Action::MoveBackward]),
+            (Axis::U, [Action::TurnLeft, Action::TurnRight]),
+            (Axis::V, [Action::LookUp, Action::LookDown]),
+        ];
+
+        Bindings {
+            keys: keys.into_iter().collect(),
+            axes: axes.into_iter().collect(),
+            dead_zone: 0.2,
+            mouse_sensitivity: 0.15,
+            invert_mouse: false,
+        }
+    }
+}
+impl Bindings {
+    // Without the file everything stays at defaults
+    pub fn bindings_loader(path: &Path) -> Result<Bindings, BindingsError> {
+        let json = match read(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Bindings::default()),
+            Err(source) => return Err(BindingsError::Io {path: path.to_path_buf(), source}),
+        };
+
+        serde_json::from_slice(&json).map_err(|e| BindingsError::Json {
+            path: path.to_path_buf(),
+            line: e.line(),
+            column: e.column(),
+            reason: json_reason(&e),
+        })
+    }
+}
+
+
+// Keyboard, mouse and joysticks turned into actions
+pub struct Input {
+    bindings: Bindings,
+    held: HashSet<Key>,
+    pressed: HashSet<Action>,   // Not yet taken by `triggered`
+
+    // Where every joystick's axes are, -1 to 1
+    axes: HashMap<(u32, Axis), f32>,
+}
+
+impl Input {
+    pub fn new(bindings: Bindings) -> Self {
+        Input {
+            bindings,
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            axes: HashMap::new(),
+        }
+    }
+
+    pub fn handle(&mut self, event: &Event) {
+        match *event {
+            Event::KeyPressed {code, ..} => {
+                let Some(action) = self.bindings.keys.get(&code) else {return;};
+                // Key repeat sends more presses of a held key
+                if self.held.insert(code) {self.pressed.insert(*action);}
+            },
+            Event::KeyReleased {code, ..} => {self.held.remove(&code);},
+
+            Event::JoystickMoved {joystickid, axis, position} => {
+                let axis = Axis::from(axis);
+                let position = (position / 100.0).clamp(-1.0, 1.0);
+                let before = self.axes.insert((joystickid, axis), position).unwrap_or(0.0);
+
+                // Pushing an axis out of the dead zone is a press of the action at that end
+                if let Some((action, _)) = self.axis_action(axis, position) {
+                    if self.axis_action(axis, before).map(|(a, _)| a) != Some(action) {self.pressed.insert(action);}
+                }
+            },
+            Event::JoystickDisconnected {joystickid} => self.axes.retain(|(j, _), _| *j != joystickid),
+
+            _ => {}
+        }
+    }
+
+    // When the window loses focus it doesn't get key releases and joystick moves anymore
+    pub fn release_all(&mut self) {
+        self.held.clear();
+        self.pressed.clear();
+        self.axes.clear();
+    }
+
+    // Action axis at `position` does and how far, 0 - 1 once out of the dead zone.
+    // None inside the dead zone or if the axis isn't bound.
+    fn axis_action(&self, axis: Axis, position: f32) -> Option<(Action, f32)> {
+        let [negative, positive] = self.bindings.axes.get(&axis)?;
+        let dead_zone = self.bindings.dead_zone;
+        if position.abs() <= dead_zone {return None;}
+
+        let amount = ((position.abs() - dead_zone) / (1.0 - dead_zone)).min(1.0);
+        Some((if position < 0.0 {*negative} else {*positive}, amount))
+    }
+
+    // How far the action goes, from 0 to 1. Keys are all or nothing, axes give anything
+    // in between. The furthest of everything bound to the action counts.
+    pub fn value(&self, action: Action) -> f32 {
+        let key = if self.held.iter().any(|key| self.bindings.keys.get(key) == Some(&action)) {1.0} else {0.0};
+        self.axes.iter()
+            .filter_map(|((_, axis), position)| self.axis_action(*axis, *position))
+            .filter(|(a, _)| *a == action)
+            .fold(key, |value, (_, amount)| value.max(amount))
+    }
+
+    // True once per press
+    pub fn triggered(&mut self, action: Action) -> bool {
+        self.pressed.remove(&action)
+    }
+
+    pub fn mouse_look(&self) -> bool {
+        self.bindings.mouse_sensitivity != 0.0
+    }
+
+    // Mouse moved by (dx, dy) pixels, gives how much to turn and look up or down, in degrees
+    pub fn mouse(&self, dx: i32, dy: i32) -> (f32, f32) {
+        let sensitivity = self.bindings.mouse_sensitivity;
+        let invert = if self.bindings.invert_mouse {-1.0} else {1.0};
+        (-dx as f32 * sensitivity, dy as f32 * sensitivity * invert)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, Bindings, Input};
+
+    use sfml::window::{joystick, Event, Key};
+
+
+    fn moved(input: &mut Input, axis: joystick::Axis, position: f32) {
+        input.handle(&Event::JoystickMoved {joystickid: 0, axis, position});
+    }
+
+    #[test]
+    fn axis_values() {
+        let mut input = Input::new(Bindings::default());
+
+        // Inside the dead zone of 0.2
+        moved(&mut input, joystick::Axis::X, 15.0);
+        assert_eq!(input.value(Action::StrafeRight), 0.0);
+
+        moved(&mut input, joystick::Axis::X, 60.0);
+        assert!((input.value(Action::StrafeRight) - 0.5).abs() < 1e-6);
+        assert_eq!(input.value(Action::StrafeLeft), 0.0);
+
+        moved(&mut input, joystick::Axis::X, -100.0);
+        assert_eq!(input.value(Action::StrafeLeft), 1.0);
+        assert_eq!(input.value(Action::StrafeRight), 0.0);
+
+        // Key held down goes all the way
+        moved(&mut input, joystick::Axis::Y, -60.0);
+        assert!((input.value(Action::MoveForward) - 0.5).abs() < 1e-6);
+        input.handle(&Event::KeyPressed {code: Key::W, alt: false, ctrl: false, shift: false, system: false});
+        assert_eq!(input.value(Action::MoveForward), 1.0);
+
+        input.release_all();
+        assert_eq!(input.value(Action::MoveForward), 0.0);
+        assert_eq!(input.value(Action::StrafeLeft), 0.0);
+    }
+
+    #[test]
+    fn axis_presses() {
+        let mut bindings = Bindings::default();
+        bindings.axes.insert(super::Axis::PovY, [Action::Use, Action::Quit]);
+        let mut input = Input::new(bindings);
+
+        moved(&mut input, joystick::Axis::PovY, -100.0);
+        assert!(input.triggered(Action::Use));
+        assert!(!input.triggered(Action::Use));
+
+        // Still pushed, no new press
+        moved(&mut input, joystick::Axis::PovY, -90.0);
+        assert!(!input.triggered(Action::Use));
+
+        moved(&mut input, joystick::Axis::PovY, 0.0);
+        moved(&mut input, joystick::Axis::PovY, -100.0);
+        assert!(input.triggered(Action::Use));
+
+        moved(&mut input, joystick::Axis::PovY, 100.0);
+        assert!(input.triggered(Action::Quit));
+        input.handle(&Event::JoystickDisconnected {joystickid: 0});
+        assert_eq!(input.value(Action::Quit), 0.0);
+    }
+}
@@ -0,0 +1,23 @@
+// The macroquad version includes this file too, input.rs needs it
+
+
+// What's wrong with a JSON file. serde_json ends its messages with "at line X column Y",
+// our errors show the position on their own, so it's cut off here.
+pub fn json_reason(e: &serde_json::Error) -> String {
+    let message = e.to_string();
+    let position = format!(" at line {} column {}", e.line(), e.column());
+    message.strip_suffix(&position).unwrap_or(&message).to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::json_reason;
+
+    #[test]
+    fn json_reason_without_position() {
+        let e = serde_json::from_str::<Vec<i32>>("[1,\n 2 3]").unwrap_err();
+        assert_eq!((e.line(), e.column()), (2, 4));
+        assert_eq!(json_reason(&e), "expected `,` or `]`");
+    }
+}
@@ -1,45 +1,116 @@
+mod config;
+mod input;
+mod json;
 mod renderer;
+mod resources;
+mod watcher;
+use crate::config::Config;
+use crate::input::{Action, Bindings, Input};
+use crate::resources::Resources;
+use crate::watcher::Watcher;
 use crate::renderer::Renderer;
 use crate::renderer::player::Player;
+use crate::renderer::Framebuffer;
 
 use sfml::{
-    system::{Vector2f, Clock},
-    window::{ContextSettings, Event, Key, Style},
-    graphics::{Color, RenderTarget, RenderWindow, View, Text, Font, Transformable},
+    system::{Vector2f, Vector2i, Clock},
+    window::{ContextSettings, Event, Style},
+    graphics::{Color, RenderTarget, RenderWindow, View, Text, Font, Transformable, Texture, Sprite},
 };
 
+use std::path::Path;
+use std::process;
+
+
+// Simulation runs in fixed steps, frames are drawn between them
+const TICK: f32 = 1.0 / 30.0;
+const MAX_LAG: f32 = 0.25;
+const FONT: &str = "fonts/arial.ttf";
+const WATCH_INTERVAL: f32 = 0.5;   // Seconds between checks for changed files
+const DEPTH_VIEW_FAR: f32 = 400.0; // Black in depth view
+
+
+// Break text into lines no longer than `width` characters
+fn wrap(text: &str, width: usize) -> String {
+    let mut lines: Vec<String> = vec![String::new()];
+    for word in text.split_whitespace() {
+        let line = lines.last_mut().unwrap();
+        if !line.is_empty() && line.len() + word.len() + 1 > width {
+            lines.push(word.to_string());
+        } else {
+            if !line.is_empty() {line.push(' ');}
+            line.push_str(word);
+        }
+    } lines.join("\n")
+}
 
-const WIDTH: u32 = 640;
-const HEIGHT: u32 = 480;
 
-const RENDER_W: f32 = 160.0;
-const RENDER_H: f32 = 120.0;
+fn main() {
+    let config = Config::load();
 
-const FPS: u32 = 30;
-const VELOCITY: f32 = 4.0;
-const FOV: i32 = 200;
+    let fail = |e: &dyn std::error::Error| -> ! {
+        eprintln!("{}", e);
+        process::exit(1);
+    };
 
+    let resources = Resources::new(&config.data, &config.pack).unwrap_or_else(|e| fail(&e));
+    let font_data = resources.read(Path::new(FONT)).unwrap_or_else(|e| fail(&e));
+    let mut renderer = Renderer::new(resources, &config.level, &config.textures, config.fov).unwrap_or_else(|e| fail(&e));
+
+    // Loading validates everything, so if we got here, level is fine
+    if config.check {
+        println!("{}: OK", config.level.display());
+        return;
+    }
+
+    let mut input = Input::new(Bindings::bindings_loader(&config.bindings).unwrap_or_else(|e| fail(&e)));
+
+    // Last failed reload, shown on screen until the level loads fine again
+    let mut load_error: Option<String> = None;
+
+    // Designers edit files in external editor, pick up their changes as soon as they're saved
+    let mut level_watcher   = Watcher::new(&config.data.join(&config.level));
+    let mut texture_watcher = Watcher::new(&config.data.join(&config.textures));
+    let mut pack_watchers: Vec<Watcher> = config.pack.iter().map(|p| Watcher::new(p)).collect();
+    let mut watch_timer: f32 = 0.0;
 
-fn main() {
     let context_settings = ContextSettings {..Default::default()};
     let mut window = RenderWindow::new(
-        (WIDTH, HEIGHT),
+        (config.width, config.height),
         "Rust Doomlike",
         Style::CLOSE,
         &context_settings,
     ); 
     
     // Scale render resolution to window resolution
-    let mut viewport = View::new(Vector2f::new(RENDER_W/2.0, RENDER_H/2.0), Vector2f::new(RENDER_W, RENDER_H));
-    viewport.set_rotation(180.0);
+    let (render_w, render_h) = (config.render_width as f32, config.render_height as f32);
+    let viewport = View::new(Vector2f::new(render_w/2.0, render_h/2.0), Vector2f::new(render_w, render_h));
     window.set_view(&viewport);
-    window.set_framerate_limit(FPS);
+    window.set_framerate_limit(config.fps);
 
     let mut player = Player::new();
-    let mut renderer = Renderer::new();
+    player.radius = config.radius;
+
+    // Safe, `font_data` lives until the end of main
+    let arial = unsafe {Font::from_memory(&font_data)}.unwrap();
+
+    // Renderer draws into plain memory, SFML only blits the finished frame
+    let mut frame = Framebuffer::new(config.render_width, config.render_height);
+    let mut screen = Texture::new().unwrap();
+    if !screen.create(frame.width, frame.height) {panic!("Can't create {}x{} texture", frame.width, frame.height);}
+
+    // Mouse look grabs the cursor
+    let center = Vector2i::new(config.width as i32 / 2, config.height as i32 / 2);
+    let mut focused = true;
+    if input.mouse_look() {
+        window.set_mouse_cursor_grabbed(true);
+        window.set_mouse_cursor_visible(false);
+        window.set_mouse_position(center);
+    }
 
-    let (mut up, mut right, mut down, mut left, mut strafe_left, mut strafe_right, mut move_up, mut move_down, mut look_up, mut look_down, mut reload_level) 
-      = (false, false, false, false, false, false, false, false, false, false, false);
+    let mut previous = player.clone();
+    let mut lag: f32 = 0.0;
+    let mut ticks: u64 = 0;     // Game clock, stops with the simulation after a stall
 
     let mut clock = Clock::start();
     loop {
@@ -48,76 +119,116 @@ fn main() {
 
         while let Some(event) = window.poll_event() {
             match event {
-                Event::Closed | Event::KeyPressed {code: Key::Escape, ..} => return,
-
-                Event::KeyPressed  {code: Key::W, ..} => up = true,
-                Event::KeyReleased {code: Key::W, ..} => up = false,
-
-                Event::KeyPressed  {code: Key::S, ..} => down = true,
-                Event::KeyReleased {code: Key::S, ..} => down = false,
-
-                Event::KeyPressed  {code: Key::D, ..} => right = true,
-                Event::KeyReleased {code: Key::D, ..} => right = false,
+                Event::Closed => return,
 
-                Event::KeyPressed  {code: Key::A, ..} => left = true,
-                Event::KeyReleased {code: Key::A, ..} => left = false,
+                // Keys released while the window is in the background never come
+                Event::LostFocus => {input.release_all(); focused = false;},
+                Event::GainedFocus => {focused = true; window.set_mouse_position(center);},
 
-                Event::KeyPressed  {code: Key::Left, ..}  => strafe_left = true,
-                Event::KeyReleased {code: Key::Left, ..}  => strafe_left = false,
-
-                Event::KeyPressed  {code: Key::Right, ..} => strafe_right = true,
-                Event::KeyReleased {code: Key::Right, ..} => strafe_right = false,
-
-                Event::KeyPressed  {code: Key::Q, ..}  => move_up = true,
-                Event::KeyReleased {code: Key::Q, ..}  => move_up = false,
-
-                Event::KeyPressed  {code: Key::E, ..}  => move_down = true,
-                Event::KeyReleased {code: Key::E, ..}  => move_down = false,
-
-                Event::KeyPressed  {code: Key::Up, ..} => look_up = true,
-                Event::KeyReleased {code: Key::Up, ..} => look_up = false,
-
-                Event::KeyPressed  {code: Key::Down, ..} => look_down = true,
-                Event::KeyReleased {code: Key::Down, ..} => look_down = false,
-
-                Event::KeyPressed  {code: Key::Enter, ..} => reload_level = true,
-                Event::KeyReleased {code: Key::Enter, ..} => reload_level = false,
-
-                _ => {}
+                _ => input.handle(&event),
+            }
+        } frame.clear(80, 100, 80);
+
+        if input.triggered(Action::Quit) {return;}
+
+        // Floors, ceilings and sprites only have depth when it's kept for every pixel
+        if input.triggered(Action::DepthView) {
+            let on = !frame.depth.per_pixel();
+            frame.depth.set_per_pixel(on);
+        }
+
+        if input.triggered(Action::Use) {
+            if let Some(w) = player.facing_wall(&renderer.level) {renderer.level.trigger(w, false);}
+        }
+
+        // Relative mouse look, cursor goes back to the middle every frame.
+        // Previous pose turns too, otherwise interpolation would lag behind the mouse.
+        if focused && input.mouse_look() {
+            let moved = window.mouse_position() - center;
+            window.set_mouse_position(center);
+
+            let (yaw, pitch) = input.mouse(moved.x, moved.y);
+            player.turn(yaw, pitch);
+            previous.turn(yaw, pitch);
+        }
+
+        // Movement doesn't depend on the frame rate. After a long stall (loading, dragging
+        // the window) the player doesn't run all the missed ticks at once.
+        lag += delta_time.min(MAX_LAG);
+        while lag >= TICK {
+            previous = player.clone();
+            for action in Action::MOVES {
+                let amount = input.value(action);
+                if amount > 0.0 {player.advance(action, TICK * amount, &renderer.level);}
+            }
+            for w in renderer.level.walls_crossed((previous.pos.x, previous.pos.y), (player.pos.x, player.pos.y)) {
+                renderer.level.trigger(w, true);
             }
-        } window.clear(Color::rgb(80, 100, 80));
-
-        if up    {player.advance(Key::W, VELOCITY);}
-        if down  {player.advance(Key::S, VELOCITY);}
-        if right {player.advance(Key::D, VELOCITY * delta_time);}
-        if left  {player.advance(Key::A, VELOCITY * delta_time);}
-        if strafe_left  {player.advance(Key::Left,  VELOCITY);}
-        if strafe_right {player.advance(Key::Right, VELOCITY);}
-        if move_up   {player.advance(Key::Q, VELOCITY * delta_time);}
-        if move_down {player.advance(Key::E, VELOCITY * delta_time);}
-        if look_up   {player.advance(Key::Up,   VELOCITY);}
-        if look_down {player.advance(Key::Down, VELOCITY);} 
-        if reload_level {renderer.reload_level();}
-
-        renderer.draw(&player, &mut window);
-
-        let arial = Font::from_file("src/fonts/arial.ttf").unwrap();
-        let mut pos_x = Text::new(&["X: ", &player.pos.x.to_string()].join(""), &arial, 18); 
-            pos_x.set_position(Vector2f::new(15.0, 20.0)); 
-            pos_x.set_scale(Vector2f::new(-0.25, -0.25));
-
-        let mut pos_y = Text::new(&["Y: ", &player.pos.y.to_string()].join(""), &arial, 18); 
-            pos_y.set_position(Vector2f::new(15.0, 15.0)); 
-            pos_y.set_scale(Vector2f::new(-0.25, -0.25));
+
+            // Player rides lifts, `update_sector` puts the camera back on the floor
+            renderer.level.update(TICK, player.pos.x, player.pos.y, player.radius);
+            player.update_sector(&renderer.level);
+            ticks += 1;
+            lag -= TICK;
+        }
+
+        watch_timer += delta_time;
+        let watch = watch_timer > WATCH_INTERVAL;
+        if watch {watch_timer = 0.0;}
+
+        // Any pack can hold textures, so a changed pack reloads everything
+        let packs_changed    = watch && pack_watchers.iter_mut().fold(false, |changed, w| w.changed() | changed);
+        let textures_changed = watch && texture_watcher.changed() | packs_changed;
+        let level_changed    = watch && level_watcher.changed();
+
+        // Reloading textures loads the level again as well
+        let reload = input.triggered(Action::Reload);
+        if reload || textures_changed || level_changed {
+            let result = if reload || textures_changed {
+                renderer.reload_textures()
+            } else {
+                renderer.reload_level().map_err(|e| e.into())
+            };
+
+            load_error = result.err().map(|e| e.to_string());
+            if let Some(e) = &load_error {eprintln!("{}", e);}
+        }
+
+        // After reloading too, the level might have changed under the player
+        player.update_sector(&renderer.level);
+        renderer.set_time(ticks as f32 * TICK + lag);
+        renderer.draw(&player.interpolate(&previous, lag / TICK, &renderer.level), &mut frame);
+        if frame.depth.per_pixel() {frame.show_depth(DEPTH_VIEW_FAR);}
+
+        // Safe as long as frame size matches the texture size
+        unsafe {screen.update_from_pixels(&frame.data, frame.width, frame.height, 0, 0);}
+        window.draw(&Sprite::with_texture(&screen));
+
+        let mut pos_x = Text::new(&format!("X: {:.0}", player.pos.x), &arial, 18); 
+            pos_x.set_position(Vector2f::new(render_w - 15.0, render_h - 20.0)); 
+            pos_x.set_scale(Vector2f::new(0.25, 0.25));
+
+        let mut pos_y = Text::new(&format!("Y: {:.0}", player.pos.y), &arial, 18); 
+            pos_y.set_position(Vector2f::new(render_w - 15.0, render_h - 15.0)); 
+            pos_y.set_scale(Vector2f::new(0.25, 0.25));
         
-        let mut pos_z = Text::new(&["Z: ", &player.pos.z.to_string()].join(""), &arial, 18); 
-            pos_z.set_position(Vector2f::new(15.0, 10.0)); 
-            pos_z.set_scale(Vector2f::new(-0.25, -0.25));
+        let mut pos_z = Text::new(&format!("Z: {:.0}", player.pos.z), &arial, 18); 
+            pos_z.set_position(Vector2f::new(render_w - 15.0, render_h - 10.0)); 
+            pos_z.set_scale(Vector2f::new(0.25, 0.25));
 
         window.draw(&pos_x);
         window.draw(&pos_y);
         window.draw(&pos_z);
 
+        if let Some(e) = &load_error {
+            let message = wrap(&["Reload failed: ", e].join(""), (render_w / 2.5) as usize);
+            let mut error = Text::new(&message, &arial, 18);
+                error.set_position(Vector2f::new(2.0, 2.0));
+                error.set_scale(Vector2f::new(0.25, 0.25));
+                error.set_fill_color(Color::RED);
+            window.draw(&error);
+        }
+
         window.display();
     }
 }
@@ -1,129 +1,277 @@
 pub mod player;
 pub mod level;
+pub mod framebuffer;
     mod wall;
     mod sector;
     mod texture;
+    mod light;
+    mod thing;
+    mod animation;
+    mod mover;
+    mod depth;
+#[cfg(test)]
+    mod fixtures;
+#[cfg(test)]
+    mod tests;
 
 pub use crate::renderer::player::Player;
-pub use crate::renderer::level::Level;
+pub use crate::renderer::level::{Level, LevelError};
+pub use crate::renderer::framebuffer::Framebuffer;
     use crate::renderer::wall::Wall;
     use crate::renderer::sector::Sector;
-    use crate::renderer::texture::Texture;
+    use crate::renderer::texture::{Texture, TextureError, TextureRef, TRANSPARENT};
+    use crate::renderer::thing::Thing;
+    use crate::renderer::animation::Animation;
 
-use super::{RENDER_W, RENDER_H, FOV};
+use thiserror::Error;
+
+use crate::resources::{Resources, PackError};
 use std::mem::swap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
 
+// Portals seen through portals seen through portals...
+const MAX_PORTAL_DEPTH: usize = 64;
 
-use sfml::{
-    graphics::{RenderWindow, Color, Vertex, VertexBufferUsage, PrimitiveType, VertexBuffer, RenderTarget}, 
-    system::Vector2f,
-};
+// Anything closer to the camera than this is cut off
+const NEAR: f32 = 1.0;
 
 
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error(transparent)]
+    Level(#[from] LevelError),
 
-pub fn pixel(x: f32, y:  f32,  r: u8, g: u8, b: u8,  draw: bool, window: &mut RenderWindow) -> Option<Vec<Vertex>> {        
-    let mut pixels = VertexBuffer::new(PrimitiveType::QUADS, 4 as u32, VertexBufferUsage::STREAM);
-    let mut verts: Vec<Vertex> = Vec::new();
-        verts.push(Vertex::new(Vector2f::new(x      , y)      , Color::rgb(r, g, b) , Vector2f::new(x      , y)));
-        verts.push(Vertex::new(Vector2f::new(x + 1.0, y)      , Color::rgb(r, g, b) , Vector2f::new(x + 1.0, y)));
-        verts.push(Vertex::new(Vector2f::new(x + 1.0, y + 1.0), Color::rgb(r, g, b) , Vector2f::new(x + 1.0, y + 1.0)));
-        verts.push(Vertex::new(Vector2f::new(x      , y + 1.0), Color::rgb(r, g, b) , Vector2f::new(x      , y + 1.0)));
-    
+    #[error(transparent)]
+    Texture(#[from] TextureError),
 
-    // If we wanna draw those quads, we can
-    // But if there's a lot of them, we can return an array
-    // and draw at once with some external VertexBuffer
-    if draw {
-        pixels.update(&verts, 0);
-        window.draw(&pixels); 
-        None
-    } else {Some(verts)}
+    #[error(transparent)]
+    Pack(#[from] PackError),
 }
 
 
-pub struct Renderer {
-    pub _sectors: i32,
-    pub sectors_data: Vec<Sector>,
+// Part of the screen seen through a chain of portals: columns `x1..x2`,
+// in every column rows from `bottom` up to `top` (not included) are still open
+struct Window {
+    x1: i32,
+    x2: i32,
+    bottom: Vec<i32>,
+    top: Vec<i32>,
+}
 
-    pub _walls: i32,
-    pub walls_data: Vec<Wall>,
+// Wall put on the screen: columns `x1..x2`, rows of the bottom (`b1`, `b2`) and top (`t1`, `t2`) at both ends.
+// `depth` and `u` of the ends (u goes from 0 at the start of the wall to 1 at the end) are after clipping
+// by the near plane, they give the texture column of every screen column.
+struct Projected {
+    x1: i32,
+    x2: i32,
+    b1: i32,
+    b2: i32,
+    t1: i32,
+    t2: i32,
+    depth: (f32, f32),
+    u: (f32, f32),
+}
+// Middle texture of a portal, left for after everything solid is drawn. `window` is the
+// part of the opening that was seen, so nearer walls already cover the rest of it.
+struct MaskedWall {
+    wall: usize,
+    sector: usize,
+    projected: Projected,
+    window: Window,
+}
 
-    textures: Vec<Texture>,
+// What `draw_portals` saw on the way: middle textures of portals, not drawn yet, and for every sector
+// the part of the screen it was seen through (None if it wasn't), like Doom's sprite clip arrays.
+// Levels made of blocks don't have `windows`.
+struct Seen {
+    masked: Vec<MaskedWall>,
+    windows: Vec<Option<Window>>,
 }
 
-impl Renderer {
-    pub fn new() -> Self {
-        let (w, s, sb, wb) = Level::level_loader();
-        let tb = Texture::texture_loader();
+// Drawn back to front once all solid walls, floors and ceilings are in the frame
+enum Masked<'a> {
+    Wall(MaskedWall),
+    Thing(f32, &'a Thing),      // Camera X, see `draw_thing`
+}
 
-        Renderer{
-            _sectors: s,
-            _walls: w,
+impl Window {
+    // Every column shut, `open` adds to it
+    fn shut(width: usize) -> Self {
+        Window {x1: 0, x2: width as i32, bottom: vec![0; width], top: vec![0; width]}
+    }
 
-            sectors_data: sb,
-            walls_data: wb,
+    // Rows open in `other` are open here too. Column has one run of open rows, so it
+    // goes from the lowest to the highest when a sector is seen through two portals.
+    fn open(&mut self, other: &Window) {
+        for x in other.x1 as usize .. other.x2 as usize {
+            let (bottom, top) = (other.bottom[x], other.top[x]);
+            if bottom >= top {continue;}
 
-            textures: tb,
+            if self.bottom[x] >= self.top[x] {(self.bottom[x], self.top[x]) = (bottom, top);}
+            else {(self.bottom[x], self.top[x]) = (self.bottom[x].min(bottom), self.top[x].max(top));}
         }
     }
+}
+
+impl Projected {
+    // Where along the wall column `x` is. Interpolated in 1/depth, which is linear
+    // on the screen, so textures don't swim on walls seen at an angle.
+    fn u_at(&self, x: i32) -> f32 {
+        let t = self.t(x);
+        let (z1, z2) = (1.0 / self.depth.0, 1.0 / self.depth.1);
+        ((1.0-t) * self.u.0 * z1 + t * self.u.1 * z2) / ((1.0-t) * z1 + t * z2)
+    }
+
+    // How far from the camera the wall is in column `x`
+    fn depth_at(&self, x: i32) -> f32 {
+        let t = self.t(x);
+        1.0 / ((1.0-t) / self.depth.0 + t / self.depth.1)
+    }
 
+    // Column `x` from 0 at `x1` to 1 at `x2`
+    fn t(&self, x: i32) -> f32 {
+        if self.x2 == self.x1 {0.0} else {(x - self.x1) as f32 / (self.x2 - self.x1) as f32}
+    }
+}
 
-    fn floor(x: i32, mut y1: i32, mut y2: i32, s: usize,  sectors: &Vec<Sector>, textures: &Vec<Texture>, player: &Player, window: &mut RenderWindow) -> Vec<Vertex> {                    
-        let offset_x = RENDER_W as i32 / 2;
-        let offset_y = RENDER_H as i32 / 2;
-        let persp_x = x - offset_x;
-        let mut wall_offset = 0;
-        let tile = sectors[s].texture_scale * 7;
-        let mut verts: Vec<Vertex> = Vec::new();
+pub struct Renderer {
+    pub level: Level,
+    textures: Vec<Texture>,
 
-        if sectors[s].surface == 1 {y2 = sectors[s].surf_arr[x as usize]; wall_offset = sectors[s].z1;}
-        if sectors[s].surface == 2 {y1 = sectors[s].surf_arr[x as usize]; wall_offset = sectors[s].z2;}
+    // Names of the level and texture directory inside `resources`
+    resources: Resources,
+    level_path: PathBuf,
+    texture_dir: PathBuf,
 
-        let mut look_up_down = -player.look_up_down as f32 * 6.28;
-        if look_up_down > RENDER_H {look_up_down = RENDER_H}
+    // Focal length for 160 pixels wide frame, scaled to the real width
+    fov: i32,
 
-        let mut move_up_down = (player.pos.z as f32 - wall_offset as f32) / offset_y as f32;
-        if move_up_down == 0.0 {move_up_down = 0.001;}
+    // Game clock in seconds, animations and scrolling textures go by it
+    time: f32,
+    // Texture shown instead of every texture, animation frames point to the frame playing now
+    frames: Vec<usize>,
 
+    // Block levels: depth of the nearest block drawn at every pixel, anything farther is clipped
+    clip: Vec<f32>,
+}
 
-        let start_y = y1 - offset_y;
-        let end_y   = y2 - offset_y;
+impl Renderer {
+    pub fn new(resources: Resources, level: &Path, textures: &Path, fov: i32) -> Result<Self, LoadError> {
+        let tb = Texture::texture_loader(&resources, textures)?;
+        let lb = Level::level_loader(&resources, level, &tb)?;
 
-        for y in start_y as i32 .. end_y as i32 {
-            let mut z = y as f32 + look_up_down;
+        Ok(Renderer{
+            level: lb,
+            textures: tb,
+            resources,
+            level_path: level.to_path_buf(),
+            texture_dir: textures.to_path_buf(),
+            fov,
+            time: 0.0,
+            frames: Vec::new(),
+            clip: Vec::new(),
+        })
+    }
+
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time;
+    }
+
+    // Texture `t` as it looks now
+    fn texture(&self, t: i32) -> &Texture {
+        &self.textures[self.frames[t as usize]]
+    }
+
+
+    // One column of the floor (z1) or the ceiling (z2) of sector `s`, `rows` are screen rows to fill
+    fn floor(&self, x: i32, rows: Range<i32>, s: usize, ceiling: bool, player: &Player, frame: &mut Framebuffer) {
+        let sector = &self.level.sectors_data[s];
+        let fov = self.fov * frame.width as i32 / 160;
+
+        let persp_x = x - frame.width as i32 / 2;
+        let wall_offset = if ceiling {sector.z2} else {sector.z1};
+        let plane = sector.plane(ceiling);
+        let tile = plane.scale * 7;
+        let offset_x = player.pos.x + plane.offset[0] + plane.scroll[0] * self.time;
+        let offset_y = player.pos.y + plane.offset[1] + plane.scroll[1] * self.time;
+
+        let (sin, cos) = player.direction();
+        let horizon = self.horizon(player, frame);
+
+        // Same units as the player position below, so floors don't slide under the walls
+        let mut move_up_down = (player.pos.z - wall_offset as f32) / 60.0;
+        if move_up_down == 0.0 {move_up_down = 0.001;}
+
+        for y in rows {
+            // Rows further from the horizon are closer to the camera
+            let mut z = y as f32 - horizon;
             if z == 0.0 {z = 0.0001}
 
             let floor_x: f32 = persp_x as f32 / z * move_up_down * tile as f32;
-            let floor_y: f32 =     FOV as f32 / z * move_up_down * tile as f32;
+            let floor_y: f32 =     fov as f32 / z * move_up_down * tile as f32;
 
-            let mut rotate_x = floor_x * player.sin[player.angle as usize] - floor_y * player.cos[player.angle as usize] + (player.pos.y as f32 / 60.0 * tile as f32);
-            let mut rotate_y = floor_x * player.cos[player.angle as usize] + floor_y * player.sin[player.angle as usize] - (player.pos.x as f32 / 60.0 * tile as f32);
+            let mut rotate_x = floor_x * sin - floor_y * cos + (offset_y / 60.0 * tile as f32);
+            let mut rotate_y = floor_x * cos + floor_y * sin - (offset_x / 60.0 * tile as f32);
 
             if rotate_x < 0.0 {rotate_x = -rotate_x + 1.0}
             if rotate_y < 0.0 {rotate_y = -rotate_y + 1.0}
 
-            let st = sectors[s].surface_texture;
-            let p = (((textures[st as usize].height - (rotate_y as i32 % textures[st as usize].height) - 1)*3) * 
-                        textures[st as usize].width + ((rotate_x as i32 % textures[st as usize].width) * 3)) as usize;
+            let texel = self.texture(plane.texture).texel(rotate_x as i32, rotate_y as i32);
+            let depth = (wall_offset as f32 - player.pos.z) * fov as f32 / z;
+            let [r, g, b] = self.lit(texel, sector.light, depth);
 
-            let r: u8  = textures[st as usize].data[p+0];
-            let g: u8  = textures[st as usize].data[p+1];
-            let b: u8  = textures[st as usize].data[p+2];
+            frame.pixel(x, y,  r,g,b);
+            frame.depth.pixel(x, y, depth);
+        }
+    }
 
+    // One column of the sky, whatever way the player looks. Texture wraps around once in 360 degrees
+    // and a texel covers the same angle up and down, texture bottom is on the horizon.
+    // Sky is always fully lit and there's no fog on it.
+    fn sky(&self, x: i32, rows: Range<i32>, p: &Player, frame: &mut Framebuffer) {
+        let Some(sky) = self.level.sky else {return;};
+        let texture = self.texture(sky);
+        let fov = (self.fov * frame.width as i32 / 160) as f32;
+        let horizon = self.horizon(p, frame);
+        let texels_per_degree = texture.width as f32 / 360.0;
+
+        let angle = p.yaw + ((x - frame.width as i32 / 2) as f32 / fov).atan().to_degrees();
+        let u = (angle * texels_per_degree) as i32;
+
+        for y in rows {
+            let elevation = ((y as f32 - horizon) / fov).atan().to_degrees();
+            let v = ((elevation * texels_per_degree) as i32).clamp(0, texture.height - 1);
+            let [r, g, b] = texture.texel(u, v);
+            frame.pixel(x, y,  r, g, b);
+        }
+    }
 
-            verts.extend(pixel((persp_x + offset_x) as f32, (y + offset_y) as f32,  r,g,b,  false, window).unwrap());
-        } verts
+    // `color` in light level `light` of a sector, `depth` away from the camera
+    fn lit(&self, color: [u8; 3], light: u8, depth: f32) -> [u8; 3] {
+        let colormap = self.level.colormap.map(|c| &self.textures[c as usize]);
+        light::lit(color, light, depth, self.level.fog.as_ref(), colormap)
     }
 
-    fn wall(&mut self, mut x1: i32, mut x2: i32,  b1: i32, b2: i32,  t1: i32, t2: i32,  s: usize,  w: i32, face: i32,  p: &Player, window: &mut RenderWindow) {
-        let width  = RENDER_W as i32;
-        let height = RENDER_H as i32;
-        let mut verts: Vec<Vertex> = Vec::new();
+    // Screen row of the horizon, where a floor or ceiling infinitely far away would be.
+    // Looking up and down moves it instead of turning the camera, so vertical lines stay vertical.
+    fn horizon(&self, p: &Player, frame: &Framebuffer) -> f32 {
+        let fov = (self.fov * frame.width as i32 / 160) as f32;
+        frame.height as f32 / 2.0 + fov * p.pitch.to_radians().tan()
+    }
 
-        let wt = self.walls_data[w as usize].texture;
-        let mut horizontal_texture: f32 = 0.0;
-        let horizontal_step: f32 = ((self.textures[wt as usize].width * self.walls_data[w as usize].v) as f32) / (x2-x1) as f32;
+    fn wall(&mut self, projected: &Projected,  s: usize,  w: i32, face: i32,  p: &Player, frame: &mut Framebuffer) {
+        let width  = frame.width  as i32;
+        let height = frame.height as i32;
+        let Projected {mut x1, mut x2, b1, b2, t1, t2, ..} = *projected;
 
+        let wall = &self.level.walls_data[w as usize];
+        let (repeat_u, repeat_v) = (wall.u, wall.v);
+        let wt = self.frames[wall.texture as usize];
+        let texture_width = (self.textures[wt].width * repeat_u) as f32;
+        let middle = wall.middle.map(|m| self.frames[m as usize]);
+        let scroll = [wall.scroll[0] * self.time, wall.scroll[1] * self.time];
 
         let delta_y_bottom = b2 - b1;
         let delta_y_top    = t2 - t1;
@@ -132,7 +280,7 @@ impl Renderer {
         let starting_x = x1;
 
         // Don't draw behind camera
-        if x1 < 1       {horizontal_texture = horizontal_texture - horizontal_step * x1 as f32; x1 = 1;}
+        if x1 < 1       {x1 = 1;}
         if x2 < 1       {x2 = 1;}
         if x1 > width-1 {x1 = width-1;}
         if x2 > width-1 {x2 = width-1;}
@@ -141,8 +289,9 @@ impl Renderer {
             let mut y1 = delta_y_bottom * (f32::floor((x - starting_x) as f32 + 0.5)) as i32 / delta_x + b1;
             let mut y2 = delta_y_top    * (f32::floor((x - starting_x) as f32 + 0.5)) as i32 / delta_x + t1;
 
-            let mut vertical_texture: f32 = 0.0;
-            let vertical_step: f32 = (self.textures[wt as usize].height as f32) / (y2-y1) as f32;
+            let horizontal_texture = projected.u_at(x) * texture_width + scroll[0];
+            let mut vertical_texture: f32 = scroll[1];
+            let vertical_step: f32 = ((self.textures[wt].height * repeat_v) as f32) / (y2-y1).max(1) as f32;
 
             // Clip Y axis - don't draw where camera doesn't see
             if y1 < 1        {vertical_texture = vertical_texture - vertical_step * y1 as f32; y1 = 1;}
@@ -152,180 +301,439 @@ impl Renderer {
 
             // Walls
             if face == 0 {
-                if self.sectors_data[s].surface == 1 {self.sectors_data[s].surf_arr[x as usize] = y1;}      // Bottom
-                if self.sectors_data[s].surface == 2 {self.sectors_data[s].surf_arr[x as usize] = y2;}      // Top
+                if self.level.sectors_data[s].surface == 1 {self.level.sectors_data[s].surf_arr[x as usize] = y1;}      // Bottom
+                if self.level.sectors_data[s].surface == 2 {self.level.sectors_data[s].surf_arr[x as usize] = y2;}      // Top
+                let light = light::wall_light(self.level.sectors_data[s].light, self.level.walls_data[w as usize].shade);
+                let depth = projected.depth_at(x);
+                frame.depth.wall(x, y1..y2, depth);
                 for y in y1..y2 {
-                    // let p1 = (((self.textures[wt as usize].height - vertical_texture as i32 - 1)*3) * 
-                    //            self.textures[wt as usize].width + (horizontal_texture as i32 * 3)) as usize;
-                    // let p2 = (((self.textures[wt as usize].height - vertical_texture as i32 - 1)*3) * 
-                    //            self.textures[wt as usize].width + ((horizontal_texture as i32 % self.textures[wt as usize].width) * 3)) as usize;
-                    let p = (((self.textures[wt as usize].height - (vertical_texture as i32 % self.textures[wt as usize].height) - 1)*3) * 
-                               self.textures[wt as usize].width + ((horizontal_texture as i32 % self.textures[wt as usize].width) * 3)) as usize;
-
-
-                    let r: u8  = self.textures[wt as usize].data[p+0] - self.walls_data[w as usize].shade/5;
-                    let g: u8  = self.textures[wt as usize].data[p+1] - self.walls_data[w as usize].shade/5;
-                    let b: u8  = self.textures[wt as usize].data[p+2] - self.walls_data[w as usize].shade/5;
-
-                    verts.extend(pixel(x as f32, y as f32,  r,g,b,  false, window).unwrap());
+                    if !Self::clipped(&mut self.clip, x, y, depth, frame) {
+                        let mut texel = self.textures[wt].texel(horizontal_texture as i32, vertical_texture as i32);
+                        if let Some(middle) = middle {
+                            let up = (vertical_texture - scroll[1]) / (self.textures[wt].height * repeat_v) as f32;
+                            let banner = masked_texel(&self.textures[middle], &self.level.walls_data[w as usize], projected.u_at(x), up, self.time);
+                            if banner != TRANSPARENT {texel = banner;}
+                        }
+                        let [r, g, b] = self.lit(texel, light, depth);
+
+                        frame.pixel(x, y,  r, g, b);
+                    }
                     vertical_texture = vertical_texture + vertical_step;
-                } horizontal_texture = horizontal_texture + horizontal_step;
+                }
             }
 
             // Top and bottom
             if face == 1 {
                 // Draw top and bottom surfaces
-                verts.extend(Self::floor(x, y1, y2, s, &self.sectors_data, &self.textures, p, window));
+                if self.level.sectors_data[s].surface == 1 {self.clipped_floor(x, y1..self.level.sectors_data[s].surf_arr[x as usize], s, false, p, frame);}
+                if self.level.sectors_data[s].surface == 2 {self.clipped_floor(x, self.level.sectors_data[s].surf_arr[x as usize]..y2, s, true,  p, frame);}
             }
 
         }
-
-        let mut pixels = VertexBuffer::new(PrimitiveType::QUADS, verts.len() as u32, VertexBufferUsage::STREAM);
-        pixels.update(&verts, 0);
-        window.draw(&pixels);
     }
 
-    fn dist(x1: i32, y1: i32,  x2: i32, y2: i32) -> i32 {
-        f32::floor(f32::sqrt(((x2-x1) as f32)*((x2-x1) as f32) + ((y2-y1) as f32)*((y2-y1) as f32))) as i32
+    // Block levels: true if a nearer block is already drawn at (x, y), otherwise the pixel is taken at `depth`
+    fn clipped(clip: &mut [f32], x: i32, y: i32, depth: f32, frame: &Framebuffer) -> bool {
+        let Some(clip) = clip.get_mut((y * frame.width as i32 + x) as usize) else {return true;};
+        if depth >= *clip {return true;}
+        *clip = depth;
+        false
     }
 
-    fn clip_behind(x1: &mut i32, y1: &mut i32, z1: &mut i32,  x2: i32, y2: i32, z2: i32) {
-        let distance_plane_pt_a = *y1 as f32;
-        let distance_plane_pt_b =  y2 as f32;
-        
-        // let mut dist = distance_plane_pt_a - distance_plane_pt_b; if dist == 0.0 {dist = 1.0;}
-        let intersection: f32 = distance_plane_pt_a / (distance_plane_pt_a - distance_plane_pt_b);
+    // `floor` for block levels, rows hidden by nearer blocks split it into runs drawn separately
+    fn clipped_floor(&mut self, x: i32, rows: Range<i32>, s: usize, ceiling: bool, p: &Player, frame: &mut Framebuffer) {
+        let sector = &self.level.sectors_data[s];
+        let z = if ceiling {sector.z2} else {sector.z1};
+        let fov = (self.fov * frame.width as i32 / 160) as f32;
+        let horizon = self.horizon(p, frame);
+
+        let mut start = rows.start;
+        for y in rows.clone() {
+            // Same depth as `floor` gives the row
+            let mut from_horizon = y as f32 - horizon;
+            if from_horizon == 0.0 {from_horizon = 0.0001}
+            let depth = (z as f32 - p.pos.z) * fov / from_horizon;
+
+            if Self::clipped(&mut self.clip, x, y, depth, frame) {
+                self.floor(x, start..y, s, ceiling, p, frame);
+                start = y + 1;
+            }
+        }
+        self.floor(x, start..rows.end, s, ceiling, p, frame);
+    }
 
-        *x1 = f32::floor((*x1 as f32) + intersection * ((x2 - (*x1)) as f32)) as i32;
-        *y1 = f32::floor((*y1 as f32) + intersection * ((y2 - (*y1)) as f32)) as i32; if *y1 == 0 {*y1 = 1;}
-        *z1 = f32::floor((*z1 as f32) + intersection * ((z2 - (*z1)) as f32)) as i32;
+    // From the player to the middle of wall `w`
+    fn wall_dist(&self, w: usize, p: &Player) -> f32 {
+        let wall = &self.level.walls_data[w];
+        let (x, y) = ((wall.x1 + wall.x2) as f32 / 2.0 - p.pos.x, (wall.y1 + wall.y2) as f32 / 2.0 - p.pos.y);
+        f32::sqrt(x*x + y*y)
     }
 
+    // Move end `a` of the wall that's behind the camera to where the wall crosses the near plane.
+    // Ends are (x, depth, u), texture coordinate moves with them.
+    fn clip_behind(a: (f32, f32, f32), b: (f32, f32, f32)) -> (f32, f32, f32) {
+        let intersection = (NEAR - a.1) / (b.1 - a.1);
+        (a.0 + intersection * (b.0 - a.0), NEAR, a.2 + intersection * (b.2 - a.2))
+    }
 
-    // Methods
-    pub fn draw(&mut self, p: &Player, window: &mut RenderWindow) {
-        let width  = RENDER_W as i32;
-        let height = RENDER_H as i32;
+    // Wall `w` from height `z1` to `z2` moved in front of the camera and put on the screen.
+    // Nothing if it's all behind the player. `back` swaps the ends, so the wall is seen from the other side.
+    fn project(&self, w: usize, z1: i32, z2: i32, back: bool, p: &Player, frame: &Framebuffer) -> Option<Projected> {
+        let width  = frame.width  as i32;
+        let fov = (self.fov * width / 160) as f32;
+        let horizon = self.horizon(p, frame);
+
+        let (sin, cos) = p.direction();
+        let wall = &self.level.walls_data[w];
+
+        // Offset by player and turn around it, second coordinate is the depth - how far it is from the camera
+        let camera = |x: i32, y: i32, u: f32| {
+            let (x, y) = (x as f32 - p.pos.x, y as f32 - p.pos.y);
+            (x*cos - y*sin, y*cos + x*sin, u)
+        };
+        let (mut a, mut b) = (camera(wall.x1, wall.y1, 0.0), camera(wall.x2, wall.y2, 1.0));
+
+        // Don't draw backfaces if we can't see them
+        if back {swap(&mut a, &mut b);}
+
+        if a.1 < NEAR && b.1 < NEAR {return None;}  // Wall behind player, don't draw it
+        if a.1 < NEAR {a = Self::clip_behind(a, b);}
+        if b.1 < NEAR {b = Self::clip_behind(b, a);}
+
+        // Screen X and Y position, Y counts from the same horizon as floors and ceilings
+        let screen_x = |(x, depth, _): (f32, f32, f32)| (x*fov/depth) as i32 + width/2;
+        let screen_y = |z: i32, depth: f32| ((z as f32 - p.pos.z)*fov/depth + horizon) as i32;
+
+        Some(Projected {
+            x1: screen_x(a), x2: screen_x(b),
+            b1: screen_y(z1, a.1), b2: screen_y(z1, b.1),
+            t1: screen_y(z2, a.1), t2: screen_y(z2, b.1),
+            depth: (a.1, b.1),
+            u: (a.2, b.2),
+        })
+    }
 
-        let mut wx: [i32; 4] = [0; 4];
-        let mut wy: [i32; 4] = [0; 4];
-        let mut wz: [i32; 4] = [0; 4];
 
-        let cos: f32 = p.cos[usize::try_from(p.angle).unwrap()];
-        let sin: f32 = p.sin[usize::try_from(p.angle).unwrap()];
+    // Levels made of solid blocks seen from outside, nearest sector goes first. Every pixel keeps
+    // the depth of the block drawn there and farther blocks are clipped by it, so blocks that are
+    // long or oddly shaped and sort in the wrong order still hide each other the right way.
+    fn draw_sorted(&mut self, p: &Player, frame: &mut Framebuffer) {
+        let width  = frame.width  as i32;
+        let height = frame.height as i32;
+        self.clip.clear();
+        self.clip.resize((width * height) as usize, f32::INFINITY);
 
         let mut cycles: i32;
 
-
-        for s in 0..(self.sectors_data.len()) as usize {
-            for w in 0..(self.sectors_data.len()-s-1) {
-                if self.sectors_data[w].dist < self.sectors_data[w+1].dist {
-                    self.sectors_data.swap(w, w+1);
-                }
-            }
+        // Average distance of the walls, this is drawing order. Nearer blocks drawn first leave less to draw.
+        for s in 0..self.level.sectors_data.len() {
+            let (ws, we) = (self.level.sectors_data[s].ws as usize, self.level.sectors_data[s].we as usize);
+            let total: f32 = (ws..we).map(|w| self.wall_dist(w, p)).sum();
+            self.level.sectors_data[s].dist = (total / (we - ws) as f32) as i32;
         }
 
-
-        for s in 0..self.sectors_data.len() {
-            self.sectors_data[s].dist = 0;   // Clear distance (drawing order)
+        // Sectors themselves stay in place, portals refer to them by index
+        let mut order: Vec<usize> = (0..self.level.sectors_data.len()).collect();
+        order.sort_by_key(|s| self.level.sectors_data[*s].dist);
 
 
+        for s in order {
             // z1 - bottom ; z2 - top
-            if p.pos.z < self.sectors_data[s].z1 {
-                self.sectors_data[s].surface = 1; 
+            if p.pos.z < self.level.sectors_data[s].z1 as f32 {
+                self.level.sectors_data[s].surface = 1; 
                 cycles = 2;
-                for x in 0..(RENDER_W as usize) {
-                    self.sectors_data[s].surf_arr[x] = RENDER_H as i32;
+                for x in 0..(width as usize) {
+                    self.level.sectors_data[s].surf_arr[x] = height;
                 }
 
-            } else if p.pos.z > self.sectors_data[s].z2 {
-                self.sectors_data[s].surface = 2; 
+            } else if p.pos.z > self.level.sectors_data[s].z2 as f32 {
+                self.level.sectors_data[s].surface = 2; 
                 cycles = 2;
-                for x in 0..(RENDER_W as usize) {
-                    self.sectors_data[s].surf_arr[x] = 0;
+                for x in 0..(width as usize) {
+                    self.level.sectors_data[s].surf_arr[x] = 0;
                 }
 
             } else {
-                self.sectors_data[s].surface = 0; 
+                self.level.sectors_data[s].surface = 0; 
                 cycles = 1;
             }
 
             for l in 0..cycles {
-                for w in self.sectors_data[s].ws .. self.sectors_data[s].we {        
-                    // Offset bottom 2 point by player
-                    let mut x1: i32 = self.walls_data[w as usize].x1 - p.pos.x; 
-                    let mut y1: i32 = self.walls_data[w as usize].y1 - p.pos.y;
-                    
-                    let mut x2: i32 = self.walls_data[w as usize].x2 - p.pos.x; 
-                    let mut y2: i32 = self.walls_data[w as usize].y2 - p.pos.y;
-                    
-                    // Don't draw backfaces if we can't see them
-                    if l == 1 {
-                        swap(&mut x1, &mut x2);
-                        swap(&mut y1, &mut y2);
-                    }
+                for w in self.level.sectors_data[s].ws .. self.level.sectors_data[s].we {
+                    let (z1, z2) = (self.level.sectors_data[s].z1, self.level.sectors_data[s].z2);
+                    let Some(projected) = self.project(w as usize, z1, z2, l == 1, p, frame) else {continue;};
+                    self.wall(&projected,  s, w, l,  p, frame);
+                }
+            }
+        }
+    }
+
+    // Levels made of rooms - start in the sector player stands in and look into the neighbours
+    // through portal walls, the way Build engine does it. Only what's visible gets drawn.
+    // Gives the middle textures of portals seen on the way and where every sector was seen
+    fn draw_portals(&self, start: usize, p: &Player, frame: &mut Framebuffer) -> Seen {
+        let width = frame.width as usize;
+        let window = Window {
+            x1: 0,
+            x2: width as i32,
+            bottom: vec![0; width],
+            top: vec![frame.height as i32; width],
+        };
+
+        let mut seen = Seen {masked: Vec::new(), windows: (0..self.level.sectors_data.len()).map(|_| None).collect()};
+        self.draw_sector(start, &window, 0, p, frame, &mut seen);
+        seen
+    }
 
-    
-                    // World X position
-                    wx[0] = f32::floor((x1 as f32)*cos  -  (y1 as f32)*sin) as i32;
-                    wx[1] = f32::floor((x2 as f32)*cos  -  (y2 as f32)*sin) as i32;
-                    wx[2] = wx[0];
-                    wx[3] = wx[1];
-            
-                    // World Y position
-                    wy[0] = f32::floor((y1 as f32)*cos  +  (x1 as f32)*sin) as i32;
-                    wy[1] = f32::floor((y2 as f32)*cos  +  (x2 as f32)*sin) as i32;   // Depth - how far wall is from the camera
-                    wy[2] = wy[0];
-                    wy[3] = wy[1];
-
-                    // Walls distance - this is drawing order
-                    self.sectors_data[s].dist = 
-                        f32::floor((self.sectors_data[s].dist + Self::dist(0, 0,  (wx[0]+wx[1])/2,  (wy[0]+wy[1])/2)) as f32) as i32;
-    
-                    // World Z height
-                    wz[0] = self.sectors_data[s].z1 - p.pos.z + ((p.look_up_down * wy[0])/32);
-                    wz[1] = self.sectors_data[s].z1 - p.pos.z + ((p.look_up_down * wy[1])/32);
-                    wz[2] = self.sectors_data[s].z2 - p.pos.z + ((p.look_up_down * wy[0])/32);
-                    wz[3] = self.sectors_data[s].z2 - p.pos.z + ((p.look_up_down * wy[1])/32);
-            
-            
-                    if wy[0] < 1 && wy[1] < 1 {continue;} // Wall behind player, don't draw it
-                    if wy[0] < 1 {
-                        let (mut x2, mut y2, mut z2) = (wx[1], wy[1], wz[1]);  
-                        Self::clip_behind(&mut wx[0], &mut wy[0], &mut wz[0],  x2, y2, z2);
-            
-                        (x2, y2, z2) = (wx[3], wy[3], wz[3]);
-                        Self::clip_behind(&mut wx[2], &mut wy[2], &mut wz[2],  x2, y2, z2);
-                    } 
-                    if wy[1] < 1 {
-                        let (mut x2, mut y2, mut z2) = (wx[0], wy[0], wz[0]);  
-                        Self::clip_behind(&mut wx[1], &mut wy[1], &mut wz[1],  x2, y2, z2);
-            
-                        (x2, y2, z2) = (wx[2], wy[2], wz[2]);
-                        Self::clip_behind(&mut wx[3], &mut wy[3], &mut wz[3],  x2, y2, z2);
+    // Walls of sector `s` facing the player, with the floor and ceiling in front of them
+    fn draw_sector(&self, s: usize, window: &Window, depth: usize, p: &Player, frame: &mut Framebuffer, seen: &mut Seen) {
+        if depth > MAX_PORTAL_DEPTH {return;}
+        seen.windows[s].get_or_insert_with(|| Window::shut(frame.width as usize)).open(window);
+        let (z1, z2) = (self.level.sectors_data[s].z1, self.level.sectors_data[s].z2);
+
+        // Farthest first, so the nearer wall wins if a concave sector overlaps itself on screen
+        let mut walls: Vec<usize> = (self.level.sectors_data[s].ws as usize .. self.level.sectors_data[s].we as usize).collect();
+        walls.sort_by(|a, b| self.wall_dist(*b, p).total_cmp(&self.wall_dist(*a, p)));
+
+        for w in walls {
+            let Some(projected) = self.project(w, z1, z2, false, p, frame) else {continue;};
+            let Projected {x1, x2, b1, b2, t1, t2, ..} = projected;
+            if x1 >= x2 {continue;}     // Facing away from the player
+
+            let (start, end) = (x1.max(window.x1), x2.min(window.x2));
+            if start >= end {continue;}
+
+            let wall = &self.level.walls_data[w];
+            let texture = self.texture(wall.texture);
+            let (upper, lower) = (self.texture(wall.upper), self.texture(wall.lower));
+            let middle = wall.middle.filter(|_| wall.portal.is_none()).map(|m| self.texture(m));
+            let scroll = [wall.scroll[0] * self.time, wall.scroll[1] * self.time];
+            let light = light::wall_light(self.level.sectors_data[s].light, wall.shade);
+
+            // Floor and ceiling of the sector behind a portal, the steps up and down to them are drawn here
+            let steps = wall.portal.and_then(|n| {
+                let next = &self.level.sectors_data[n as usize];
+                self.project(w, next.z1, next.z2, false, p, frame)
+            });
+
+            // What's left open behind a portal
+            let mut next = Window {x1: start, x2: end, bottom: window.bottom.clone(), top: window.top.clone()};
+
+            for x in start..end {
+                let (bottom, top) = (window.bottom[x as usize], window.top[x as usize]);
+                if bottom >= top {continue;}
+
+                let y1 = (b2-b1) * (x-x1) / (x2-x1) + b1;
+                let y2 = (t2-t1) * (x-x1) / (x2-x1) + t1;
+                let (wall_bottom, wall_top) = (y1.clamp(bottom, top), y2.clamp(bottom, top));
+
+                if p.pos.z > z1 as f32 {self.floor(x, bottom..wall_bottom, s, false, p, frame);}
+                if self.level.sectors_data[s].sky {self.sky(x, wall_top..top, p, frame);}
+                else if p.pos.z < z2 as f32 {self.floor(x, wall_top..top, s, true, p, frame);}
+
+                // Solid walls are all wall, portals only have the steps and the opening between them
+                let (part, upper_part) = match &steps {
+                    None => ((texture, wall_bottom..wall_top), None),
+                    Some(n) => {
+                        let step_bottom = ((n.b2-n.b1) * (x-x1) / (x2-x1) + n.b1).clamp(wall_bottom, wall_top);
+                        let step_top = ((n.t2-n.t1) * (x-x1) / (x2-x1) + n.t1).clamp(step_bottom, wall_top);
+                        next.bottom[x as usize] = step_bottom;
+                        next.top[x as usize] = step_top;
+                        ((lower, wall_bottom..step_bottom), Some((upper, step_top..wall_top)))
+                    },
+                };
+
+                // Textures are laid over the whole height of the sector, steps show their part of it
+                let depth = projected.depth_at(x);
+                for (texture, rows) in std::iter::once(part).chain(upper_part) {
+                    if rows.is_empty() {continue;}
+
+                    let horizontal_texture = projected.u_at(x) * (texture.width * wall.u) as f32 + scroll[0];
+                    let vertical_step: f32 = (texture.height * wall.v) as f32 / (y2-y1).max(1) as f32;
+                    let mut vertical_texture = (rows.start-y1) as f32 * vertical_step + scroll[1];
+                    frame.depth.wall(x, rows.clone(), depth);
+                    for y in rows {
+                        let mut texel = texture.texel(horizontal_texture as i32, vertical_texture as i32);
+                        if let Some(middle) = middle {
+                            let up = (vertical_texture - scroll[1]) / (texture.height * wall.v) as f32;
+                            let banner = masked_texel(middle, wall, projected.u_at(x), up, self.time);
+                            if banner != TRANSPARENT {texel = banner;}
+                        }
+                        let [r, g, b] = self.lit(texel, light, depth);
+                        frame.pixel(x, y,  r, g, b);
+                        vertical_texture += vertical_step;
                     }
-            
-                    // Screen X and Y position
-                    wx[0] = wx[0]*FOV / wy[0]+(width/2);  wy[0] = wz[0]*FOV / wy[0]+(height/2);
-                    wx[1] = wx[1]*FOV / wy[1]+(width/2);  wy[1] = wz[1]*FOV / wy[1]+(height/2);
-                    wx[2] = wx[2]*FOV / wy[2]+(width/2);  wy[2] = wz[2]*FOV / wy[2]+(height/2);
-                    wx[3] = wx[3]*FOV / wy[3]+(width/2);  wy[3] = wz[3]*FOV / wy[3]+(height/2);
-            
-                    
-                    let (x1, x2,  y1, y2,  y3, y4) = (wx[0], wx[1],  wy[0], wy[1],  wy[2], wy[3]);
-                    self.wall(x1, x2,  y1, y2,  y3, y4,  s, w, l,  p, window);
                 }
-    
-                self.sectors_data[s].dist = self.sectors_data[s].dist  /  (self.sectors_data[s].we - self.sectors_data[s].ws); 
+            }
+
+            if let Some(portal) = wall.portal {
+                self.draw_sector(portal as usize, &next, depth + 1, p, frame, seen);
+                if wall.middle.is_some() {seen.masked.push(MaskedWall {wall: w, sector: s, projected, window: next});}
             }
         }
     }
 
-    pub fn reload_level(&mut self) {
-        let (w, s, sb, wb) = Level::level_loader();
-            self._sectors = s;
-            self._walls = w;
-            self.sectors_data = sb;
-            self.walls_data = wb;
+
+    // Things and middle textures of portals, farthest first, so each one covers what's behind it.
+    // Things are sprites turned to the camera, in every column a sprite is hidden
+    // behind the walls drawn there that are closer to the camera. In levels of rooms it's also cut
+    // to where its sector was seen, so floors and steps in front of it cover it too.
+    fn draw_masked(&self, seen: Seen, p: &Player, frame: &mut Framebuffer) {
+        let Seen {masked: walls, windows} = seen;
+        let (sin, cos) = p.direction();
+
+        // Position in front of the camera, same as walls in `project`
+        let mut masked: Vec<(f32, Masked)> = self.level.things.iter().map(|thing| {
+            let (x, y) = (thing.x - p.pos.x, thing.y - p.pos.y);
+            (y*cos + x*sin, Masked::Thing(x*cos - y*sin, thing))
+        }).filter(|(depth, _)| *depth >= NEAR).collect();
+        masked.extend(walls.into_iter().map(|wall| ((wall.projected.depth.0 + wall.projected.depth.1) / 2.0, Masked::Wall(wall))));
+        masked.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        for (depth, item) in masked {
+            match item {
+                Masked::Thing(x, thing) => {
+                    let clip = match thing.sector.and_then(|s| windows.get(s as usize)) {
+                        Some(None) => continue,     // Its sector wasn't seen at all
+                        Some(Some(window)) => Some(window),
+                        None => None,
+                    };
+                    self.draw_thing(x, depth, thing, clip, p, frame);
+                },
+                Masked::Wall(wall) => self.draw_masked_wall(&wall, frame),
+            }
+        }
     }
-}
\ No newline at end of file
+
+    // Thing `x` to the side and `depth` in front of the camera, only drawn inside `clip` if there's one
+    fn draw_thing(&self, x: f32, depth: f32, thing: &Thing, clip: Option<&Window>, p: &Player, frame: &mut Framebuffer) {
+        let width = frame.width as i32;
+        let fov = (self.fov * width / 160) as f32;
+        let horizon = self.horizon(p, frame);
+
+        let texture = self.texture(thing.sprite);
+        let (z, light) = match thing.sector {
+            Some(s) => (self.level.sectors_data[s as usize].z1, self.level.sectors_data[s as usize].light),
+            None    => (0, light::FULL_LIGHT),
+        };
+
+        // Screen rectangle of the sprite, `x1` is its left edge (renderer X grows to the left on screen)
+        let size = fov / depth * thing.scale;
+        let center = x*fov/depth + (width/2) as f32;
+        let (x1, x2) = (center + texture.width as f32 * size / 2.0, center - texture.width as f32 * size / 2.0);
+        let bottom = (z as f32 - p.pos.z)*fov/depth + horizon;
+
+        // Angle from the thing's front to the camera, 0 is right in front of it
+        let viewer = (p.pos.x - thing.x).atan2(p.pos.y - thing.y).to_degrees();
+        let mirrored = ((viewer - thing.angle).rem_euclid(360.0) - 180.0).abs() < 90.0;
+
+        for column in (x2.ceil() as i32).max(0) .. (x1.ceil() as i32).min(width) {
+            let mut u = ((x1 - column as f32) / size) as i32;
+            if mirrored {u = texture.width - 1 - u;}
+
+            // In front of every wall in the column, no need to look row by row
+            let in_front = !frame.depth.per_pixel() && depth < frame.depth.column(column);
+
+            let (low, high) = clip.map_or((0, frame.height as i32), |w| (w.bottom[column as usize], w.top[column as usize]));
+            let rows = (bottom.ceil() as i32).max(low) .. ((bottom + texture.height as f32 * size).ceil() as i32).min(high);
+            for y in rows {
+                if !in_front && !frame.depth.visible(column, y, depth) {continue;}
+
+                let v = ((y as f32 - bottom) / size) as i32;
+                let texel = texture.texel(u, v);
+                if texel == TRANSPARENT {continue;}
+
+                let [r, g, b] = self.lit(texel, light, depth);
+                frame.pixel(column, y,  r, g, b);
+                frame.depth.pixel(column, y, depth);
+            }
+        }
+    }
+
+    // Middle texture across the opening of a portal, only where it was seen
+    fn draw_masked_wall(&self, masked: &MaskedWall, frame: &mut Framebuffer) {
+        let MaskedWall {wall: w, sector: s, ref projected, ref window} = *masked;
+        let Projected {x1, x2, b1, b2, t1, t2, ..} = *projected;
+        let wall = &self.level.walls_data[w];
+        let Some(m) = wall.middle else {return;};
+        let middle = self.texture(m);
+        let light = light::wall_light(self.level.sectors_data[s].light, wall.shade);
+
+        for x in window.x1..window.x2 {
+            let y1 = (b2-b1) * (x-x1) / (x2-x1) + b1;
+            let y2 = (t2-t1) * (x-x1) / (x2-x1) + t1;
+            let along = projected.u_at(x);
+            let depth = projected.depth_at(x);
+
+            for y in window.bottom[x as usize]..window.top[x as usize] {
+                let up = (y - y1) as f32 / (y2-y1).max(1) as f32;
+                let texel = masked_texel(middle, wall, along, up, self.time);
+                if texel == TRANSPARENT {continue;}
+
+                let [r, g, b] = self.lit(texel, light, depth);
+                frame.pixel(x, y,  r, g, b);
+                frame.depth.pixel(x, y, depth);
+            }
+        }
+    }
+
+
+    // Methods
+    pub fn draw(&mut self, p: &Player, frame: &mut Framebuffer) {
+        // Surface points are stored per screen column
+        for sector in self.level.sectors_data.iter_mut() {
+            sector.surf_arr.resize(frame.width as usize, 0);
+        }
+        frame.depth.clear();
+
+        // Animations move every frame in them `step` frames on
+        self.frames = (0..self.textures.len()).collect();
+        for animation in &self.level.animations {
+            let step = (self.time * animation.rate) as usize;
+            for (i, texture) in animation.frames.iter().enumerate() {
+                self.frames[*texture as usize] = animation.frames[(i + step) % animation.frames.len()] as usize;
+            }
+        }
+
+        // Standing inside a sector means it's a room, otherwise every sector is a block seen from outside
+        let seen = match p.sector {
+            Some(s) => self.draw_portals(s, p, frame),
+            None    => {
+                // Blocks stand under the open sky, if the level has one
+                for x in 0..frame.width as i32 {self.sky(x, 0..frame.height as i32, p, frame);}
+                self.draw_sorted(p, frame);
+                // Blocks have no portals, middle textures are painted over their walls
+                Seen {masked: Vec::new(), windows: Vec::new()}
+            },
+        };
+
+        self.draw_masked(seen, p, frame);
+    }
+
+    // On error the level that's already loaded stays in place
+    pub fn reload_level(&mut self) -> Result<(), LevelError> {
+        self.level = Level::level_loader(&self.resources, &self.level_path, &self.textures)?;
+        Ok(())
+    }
+
+    // Resource packs and level are loaded again too, level has to be valid with the new set of textures.
+    // Nothing changes unless everything loads fine.
+    pub fn reload_textures(&mut self) -> Result<(), LoadError> {
+        let resources = self.resources.reopen()?;
+        let tb = Texture::texture_loader(&resources, &self.texture_dir)?;
+        let lb = Level::level_loader(&resources, &self.level_path, &tb)?;
+            self.level = lb;
+            self.textures = tb;
+            self.resources = resources;
+        Ok(())
+    }
+}
+
+
+// Texel of masked texture `middle` repeated and scrolled over `wall` like its other textures,
+// `along` and `up` the wall go from 0 to 1
+fn masked_texel(middle: &Texture, wall: &Wall, along: f32, up: f32, time: f32) -> [u8; 3] {
+    let u = along * (middle.width * wall.u) as f32 + wall.scroll[0] * time;
+    let v = up * (middle.height * wall.v) as f32 + wall.scroll[1] * time;
+    middle.texel(u as i32, v as i32)
+}
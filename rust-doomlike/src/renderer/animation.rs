@@ -0,0 +1,17 @@
+use serde::Deserialize;
+
+use super::TextureRef;
+
+
+// Textures shown one after another - water, lava, computer screens.
+// Wherever the level uses one of the frames, the animation plays from that frame on.
+#[derive(Deserialize, Debug)]
+pub struct Animation {
+    #[serde(rename = "frames")]
+    pub frames_ref: Vec<TextureRef>,
+    #[serde(skip)]
+    pub frames: Vec<i32>,   // Resolved `frames_ref`
+
+    // Frames per second
+    pub rate: f32,
+}
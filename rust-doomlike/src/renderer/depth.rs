@@ -0,0 +1,140 @@
+use std::ops::Range;
+
+
+// Rows `bottom..top` of a screen column covered by a wall `depth` away
+#[derive(Clone)]
+struct Span {
+    depth: f32,
+    bottom: i32,
+    top: i32,
+}
+
+// How far from the camera is what's been drawn in the frame, in the same coordinates as
+// `Framebuffer::pixel`. Walls are always kept per column. Depth of every pixel (floors,
+// ceilings and sprites too) costs a bit more, so it's only there when turned on.
+// Nothing drawn (sky, background) is infinitely far.
+pub struct DepthBuffer {
+    width: i32,
+    height: i32,
+    columns: Vec<Vec<Span>>,
+    pixels: Option<Vec<f32>>,
+}
+
+impl DepthBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        DepthBuffer {
+            width: width as i32,
+            height: height as i32,
+            columns: vec![Vec::new(); width as usize],
+            pixels: None,
+        }
+    }
+
+    // Renderer does it before every frame
+    pub fn clear(&mut self) {
+        for column in self.columns.iter_mut() {column.clear();}
+        if let Some(pixels) = self.pixels.as_mut() {pixels.fill(f32::INFINITY);}
+    }
+
+    // Keep depth of every pixel from the next frame on, or only walls
+    pub fn set_per_pixel(&mut self, on: bool) {
+        self.pixels = if on {Some(vec![f32::INFINITY; (self.width * self.height) as usize])} else {None};
+    }
+
+    pub fn per_pixel(&self) -> bool {
+        self.pixels.is_some()
+    }
+
+    // Wall drawn in column `x`
+    pub fn wall(&mut self, x: i32, rows: Range<i32>, depth: f32) {
+        if x < 0 || x >= self.width {return;}
+        self.columns[x as usize].push(Span {depth, bottom: rows.start, top: rows.end});
+        if self.pixels.is_none() {return;}
+        for y in rows {self.pixel(x, y, depth);}
+    }
+
+    // Anything else drawn, only kept per pixel
+    pub fn pixel(&mut self, x: i32, y: i32, depth: f32) {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {return;}
+        let i = (y * self.width + x) as usize;
+        if let Some(pixels) = self.pixels.as_mut() {pixels[i] = pixels[i].min(depth);}
+    }
+
+    // Closest wall in column `x`, wherever it is in the column
+    pub fn column(&self, x: i32) -> f32 {
+        if x < 0 || x >= self.width {return f32::INFINITY;}
+        self.columns[x as usize].iter().fold(f32::INFINITY, |nearest, span| nearest.min(span.depth))
+    }
+
+    // Closest thing drawn at pixel (x, y), only walls if there's no depth per pixel
+    pub fn at(&self, x: i32, y: i32) -> f32 {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {return f32::INFINITY;}
+        match &self.pixels {
+            Some(pixels) => pixels[(y * self.width + x) as usize],
+            None => self.columns[x as usize].iter()
+                .filter(|span| span.bottom <= y && y < span.top)
+                .fold(f32::INFINITY, |nearest, span| nearest.min(span.depth)),
+        }
+    }
+
+    // Would something `depth` away drawn at (x, y) be seen
+    pub fn visible(&self, x: i32, y: i32, depth: f32) -> bool {
+        depth < self.at(x, y)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::DepthBuffer;
+
+    const INF: f32 = f32::INFINITY;
+
+
+    // 4x4 with two walls in column 1, the closer one only over rows 1 and 2
+    fn buffer(per_pixel: bool) -> DepthBuffer {
+        let mut depth = DepthBuffer::new(4, 4);
+        depth.set_per_pixel(per_pixel);
+        depth.wall(1, 0..4, 20.0);
+        depth.wall(1, 1..3, 10.0);
+        depth.wall(9, 0..4, 5.0);
+        depth.pixel(2, 0, 15.0);
+        depth
+    }
+
+    #[test]
+    fn walls() {
+        for per_pixel in [false, true] {
+            let depth = buffer(per_pixel);
+            assert_eq!(depth.column(1), 10.0);
+            assert_eq!([0, 1, 2, 3].map(|y| depth.at(1, y)), [20.0, 10.0, 10.0, 20.0]);
+            assert_eq!([depth.column(0), depth.at(0, 0), depth.column(-1), depth.at(1, 4)], [INF; 4]);
+
+            assert!(depth.visible(1, 0, 15.0));
+            assert!(!depth.visible(1, 1, 15.0));
+            assert!(!depth.visible(1, 1, 10.0));
+        }
+    }
+
+    #[test]
+    fn pixels() {
+        let mut depth = buffer(false);
+        assert!(!depth.per_pixel());
+        assert_eq!((depth.at(2, 0), depth.column(2)), (INF, INF));
+
+        let mut with_pixels = buffer(true);
+        assert!(with_pixels.per_pixel());
+        assert_eq!((with_pixels.at(2, 0), with_pixels.column(2)), (15.0, INF));
+        assert!(!with_pixels.visible(2, 0, 16.0));
+
+        // Closest one stays
+        with_pixels.pixel(1, 0, 30.0);
+        with_pixels.pixel(2, 0, 5.0);
+        assert_eq!((with_pixels.at(1, 0), with_pixels.at(2, 0)), (20.0, 5.0));
+
+        for d in [&mut depth, &mut with_pixels] {
+            d.clear();
+            assert_eq!((d.column(1), d.at(1, 1), d.at(2, 0)), (INF, INF, INF));
+        }
+    }
+}
@@ -0,0 +1,36 @@
+// Small levels and parts of them for unit tests
+use super::wall::Wall;
+use super::mover::Mover;
+
+use serde_json::{json, Value};
+
+
+pub fn wall_json(x1: i32, y1: i32, x2: i32, y2: i32) -> Value {
+    json!({"x1": x1, "y1": y1, "x2": x2, "y2": y2, "texture": "T1", "u": 1, "v": 1, "shade": 0})
+}
+
+pub fn wall(x1: i32, y1: i32, x2: i32, y2: i32) -> Wall {
+    serde_json::from_value(wall_json(x1, y1, x2, y2)).unwrap()
+}
+
+pub fn mover(kind: &str, height: i32, speed: f32, wait: f32) -> Mover {
+    serde_json::from_value(json!({"tag": 1, "kind": kind, "height": height, "speed": speed, "wait": wait})).unwrap()
+}
+
+// Room 0..64 x 0..64 with floor 0 and ceiling 64, open at x = 64 to a room with `floor` and `ceiling`
+pub fn two_rooms(floor: i32, ceiling: i32) -> Value {
+    let sector = |ws, we, z1, z2| json!({"ws": ws, "we": we, "z1": z1, "z2": z2, "surf_arr": [], "surface": 0,
+                                         "floor_texture": "T1", "ceiling_texture": "T1", "dist": 0});
+    let mut level = json!({
+        "sectors": 2,
+        "sectors_data": [sector(0, 4, 0, 64), sector(4, 8, floor, ceiling)],
+        "walls": 8,
+        "walls_data": [
+            wall_json(0, 0, 0, 64), wall_json(0, 64, 64, 64), wall_json(64, 64, 64, 0), wall_json(64, 0, 0, 0),
+            wall_json(64, 0, 64, 64), wall_json(64, 64, 128, 64), wall_json(128, 64, 128, 0), wall_json(128, 0, 64, 0),
+        ],
+    });
+    level["walls_data"][2]["portal"] = json!(1);
+    level["walls_data"][4]["portal"] = json!(0);
+    level
+}
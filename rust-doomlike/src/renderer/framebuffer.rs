@@ -0,0 +1,58 @@
+use super::depth::DepthBuffer;
+
+
+// Plain RGBA image the renderer draws into.
+// It doesn't know anything about SFML (or any window at all), so frames
+// can be rendered on a headless machine and blitted to the screen later.
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+
+    // RGBA, 4 bytes per pixel, rows go top to bottom
+    pub data: Vec<u8>,
+
+    // How far everything drawn is, for whatever is drawn later and has to hide behind it
+    pub depth: DepthBuffer,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Framebuffer {
+            width,
+            height,
+            data: vec![255; (width * height * 4) as usize],
+            depth: DepthBuffer::new(width, height),
+        }
+    }
+
+
+    pub fn clear(&mut self, r: u8, g: u8, b: u8) {
+        for p in self.data.chunks_exact_mut(4) {
+            p.copy_from_slice(&[r, g, b, 255]);
+        }
+    }
+
+    // Renderer space has its origin in the bottom right corner
+    // (we used to rotate SFML view by 180 degrees to get it),
+    // so here we flip both axes to get the image the right way up
+    pub fn pixel(&mut self, x: i32, y: i32,  r: u8, g: u8, b: u8) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {return;}
+
+        let col = self.width  as i32 - x - 1;
+        let row = self.height as i32 - y - 1;
+        let p = ((row * self.width as i32 + col) * 4) as usize;
+
+        self.data[p..p+4].copy_from_slice(&[r, g, b, 255]);
+    }
+
+    // Replace the image with its depth, white is close and black is `far` away or more.
+    // Only walls unless depth is kept for every pixel.
+    pub fn show_depth(&mut self, far: f32) {
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let grey = (255.0 * (1.0 - self.depth.at(x, y) / far).max(0.0)) as u8;
+                self.pixel(x, y,  grey, grey, grey);
+            }
+        }
+    }
+}
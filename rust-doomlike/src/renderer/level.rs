@@ -1,36 +1,465 @@
 use serde::{Deserialize};
+use thiserror::Error;
 
-use std::error::Error;
-use std::fs::File;
-use std::io::BufReader;
+use std::io;
+use std::path::{Path, PathBuf};
 
-use super::Wall;
+use crate::json::json_reason;
+use crate::resources::Resources;
+
+use super::{TextureRef, Wall};
 use super::Sector;
-use super::RENDER_W;
+use super::Thing;
+use super::Animation;
+use super::Texture;
+use super::light::Fog;
+use super::mover::Kind;
+use super::player::HEIGHT;
+
+
+#[derive(Debug, Error)]
+pub enum LevelError {
+    #[error("{}: {source}", path.display())]
+    Io {path: PathBuf, source: io::Error},
+
+    // Bad syntax, missing fields, wrong types etc.
+    #[error("{}:{line}:{column}: {reason}", path.display())]
+    Json {path: PathBuf, line: usize, column: usize, reason: String},
+
+    // JSON is fine, but the data doesn't make sense (see `Level::validate`)
+    #[error("{}: {} problem(s) found\n  {}", path.display(), problems.len(), problems.join("\n  "))]
+    Invalid {path: PathBuf, problems: Vec<String>},
+}
 
 #[derive(Deserialize, Debug)]
 pub struct Level {
     sectors: i32,
-    sectors_data: Vec<Sector>,
+    pub sectors_data: Vec<Sector>,
 
     walls: i32,
-    walls_data: Vec<Wall>,
+    pub walls_data: Vec<Wall>,
+
+    #[serde(default)]
+    pub things: Vec<Thing>,
+
+    #[serde(default)]
+    pub animations: Vec<Animation>,
+
+    // No fog unless the level asks for it, `"fog": {}` gives black fog 1024 units away
+    #[serde(default)]
+    pub fog: Option<Fog>,
+
+    // Panoramic texture going once around 360 degrees, seen through the ceiling of sectors with `sky`
+    // and behind everything in levels made of blocks
+    #[serde(default, rename = "sky")]
+    sky_ref: Option<TextureRef>,
+    #[serde(skip)]
+    pub sky: Option<i32>,       // Resolved `sky_ref`
+
+    // Texture used as a colormap for light levels (see `light::lit`), none by default
+    #[serde(default, rename = "colormap")]
+    colormap_ref: Option<TextureRef>,
+    #[serde(skip)]
+    pub colormap: Option<i32>,      // Resolved `colormap_ref`
 }
 impl Level {
-    fn data_loader() -> Result<Level, Box<dyn Error>> {
-        let file = File::open("src/levels/level.lvl")?;
-        let reader = BufReader::new(file);
-        let mut level: Level = serde_json::from_reader(reader).expect("Bad JSON file");
-        
-        for sector in level.sectors_data.iter_mut() {
-            sector.surf_arr = vec![0; RENDER_W as usize];
-        }
+    fn data_loader(resources: &Resources, name: &Path) -> Result<Level, LevelError> {
+        let path = resources.describe(name);
+        let json = resources.read(name).map_err(|source| LevelError::Io {path: path.clone(), source})?;
+        serde_json::from_slice(&json).map_err(|e| LevelError::Json {
+            path,
+            line: e.line(),
+            column: e.column(),
+            reason: json_reason(&e),
+        })
+    }
+
+    pub fn level_loader(resources: &Resources, name: &Path, textures: &[Texture]) -> Result<Level, LevelError> {
+        let mut level = Self::data_loader(resources, name)?;
+        level.resolve_textures(textures);
+
+        let problems = level.validate(textures);
+        if !problems.is_empty() {return Err(LevelError::Invalid {path: resources.describe(name), problems});}
 
         Ok(level)
     }
 
-    pub fn level_loader() -> (i32, i32, Vec<Sector>, Vec<Wall>) {
-        let level = Self::data_loader().unwrap();
-        (level.sectors, level.walls,  level.sectors_data, level.walls_data)
+    // Sector with point (x, y) inside its walls, the first one if floor plans overlap
+    pub fn sector_at(&self, x: f32, y: f32) -> Option<usize> {
+        self.sectors_data.iter().position(|sector| sector.contains(&self.walls_data, x, y))
+    }
+
+    // Wall `w` used by the player or walked over (`walked`), starts sectors with the tag of its trigger
+    pub fn trigger(&mut self, w: usize, walked: bool) {
+        let Some(trigger) = &self.walls_data[w].trigger else {return;};
+        if trigger.walk != walked {return;}
+
+        let tag = trigger.tag;
+        for sector in self.sectors_data.iter_mut() {
+            let Some(mover) = sector.mover.as_mut() else {continue;};
+            if mover.tag == tag {mover.activate(if mover.kind == Kind::Lift {sector.z1} else {sector.z2});}
+        }
+    }
+
+    // Walls with a walk trigger on the way from `from` to `to`
+    pub fn walls_crossed(&self, from: (f32, f32), to: (f32, f32)) -> Vec<usize> {
+        (0..self.walls_data.len()).filter(|w| {
+            let wall = &self.walls_data[*w];
+            wall.trigger.as_ref().is_some_and(|t| t.walk) && wall.crossed_by(from, to).is_some()
+        }).collect()
+    }
+
+    // Move sectors by `time` seconds. A ceiling doesn't come down on the player at (x, y),
+    // in any sector the player's `radius` reaches into.
+    pub fn update(&mut self, time: f32, x: f32, y: f32, radius: f32) {
+        for sector in self.sectors_data.iter_mut() {
+            let lowest = if sector.overlaps(&self.walls_data, x, y, radius) {sector.z1 + HEIGHT} else {sector.z1};
+            let Some(mover) = sector.mover.as_mut() else {continue;};
+
+            let Some(z) = mover.advance(time, lowest) else {continue;};
+            if mover.kind == Kind::Lift {sector.z1 = z;} else {sector.z2 = z;}
+        }
+    }
+
+    // Turn texture names into indices, unknown ones become -1 and `validate` reports them
+    fn resolve_textures(&mut self, textures: &[Texture]) {
+        for sector in self.sectors_data.iter_mut() {
+            sector.resolve_textures(textures);
+        }
+        for wall in self.walls_data.iter_mut() {
+            wall.texture = wall.texture_ref.resolve(textures);
+            wall.upper = wall.upper_ref.as_ref().map_or(wall.texture, |u| u.resolve(textures));
+            wall.lower = wall.lower_ref.as_ref().map_or(wall.texture, |l| l.resolve(textures));
+            wall.middle = wall.middle_ref.as_ref().map(|m| m.resolve(textures));
+        }
+        self.colormap = self.colormap_ref.as_ref().map(|c| c.resolve(textures));
+        self.sky = self.sky_ref.as_ref().map(|c| c.resolve(textures));
+        for thing in self.things.iter_mut() {
+            thing.sprite = thing.sprite_ref.resolve(textures);
+        }
+        for animation in self.animations.iter_mut() {
+            animation.frames = animation.frames_ref.iter().map(|f| f.resolve(textures)).collect();
+        }
+    }
+
+    // Everything renderer relies on and JSON parser can't check.
+    // `textures` are the loaded textures, texture names have to be resolved already.
+    pub fn validate(&self, textures: &[Texture]) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+        let walls = self.walls_data.len() as i32;
+
+        if self.sectors != self.sectors_data.len() as i32 {
+            problems.push(format!("`sectors` is {}, but there are {} sectors in `sectors_data`", self.sectors, self.sectors_data.len()));
+        }
+        if self.walls != walls {
+            problems.push(format!("`walls` is {}, but there are {} walls in `walls_data`", self.walls, walls));
+        }
+
+        for (s, sector) in self.sectors_data.iter().enumerate() {
+            if sector.ws < 0 || sector.we > walls || sector.ws >= sector.we {
+                problems.push(format!("sector {}: walls {}..{} are not a valid range in `walls_data` (0..{})", s, sector.ws, sector.we, walls));
+            } else {
+                // Every wall has to start where the previous one ended, last one closes the loop
+                for w in sector.ws..sector.we {
+                    let next = if w + 1 == sector.we {sector.ws} else {w + 1};
+                    let (a, b) = (&self.walls_data[w as usize], &self.walls_data[next as usize]);
+
+                    if (a.x2, a.y2) != (b.x1, b.y1) {
+                        problems.push(format!("sector {}: walls don't form a closed loop, wall {} ends at ({}, {}) but wall {} starts at ({}, {})",
+                                              s, w, a.x2, a.y2, next, b.x1, b.y1));
+                    }
+
+                    if let Some(portal) = a.portal {
+                        if portal < 0 || portal >= self.sectors_data.len() as i32 || portal == s as i32 {
+                            problems.push(format!("sector {}: wall {} is a portal to sector {}, which isn't another sector of the level", s, w, portal));
+                        }
+                    }
+                }
+            }
+
+            // Closed door is the only sector without any room inside
+            if sector.z1 > sector.z2 || (sector.z1 == sector.z2 && sector.mover.is_none()) {
+                problems.push(format!("sector {}: bottom z1 ({}) has to be below top z2 ({})", s, sector.z1, sector.z2));
+            }
+            if let Some(mover) = &sector.mover {
+                let (plane, z, goes) = match mover.kind {
+                    Kind::Door    => ("ceiling", sector.z2, mover.height > sector.z2),
+                    Kind::Lift    => ("floor", sector.z1, mover.height < sector.z1),
+                    Kind::Crusher => ("ceiling", sector.z2, mover.height < sector.z2 && mover.height >= sector.z1),
+                };
+                if !goes {
+                    problems.push(format!("sector {}: {:?} can't move its {} from {} to height {}", s, mover.kind, plane, z, mover.height));
+                }
+                if mover.speed <= 0.0 {
+                    problems.push(format!("sector {}: mover speed {} has to be above 0", s, mover.speed));
+                }
+                if mover.wait < 0.0 {
+                    problems.push(format!("sector {}: mover wait {} can't be below 0", s, mover.wait));
+                }
+            }
+            for (name, texture, texture_ref) in [("floor_texture", sector.floor_texture, sector.floor_texture_ref()),
+                                                 ("ceiling_texture", sector.ceiling_texture, sector.ceiling_texture_ref())] {
+                match texture_ref {
+                    None => problems.push(format!("sector {}: has neither {} nor surface_texture", s, name)),
+                    Some(r) if texture < 0 || texture >= textures.len() as i32 => {
+                        problems.push(format!("sector {}: {} {} doesn't exist ({} textures loaded)", s, name, r, textures.len()));
+                    },
+                    Some(_) => {}
+                }
+            }
+            if sector.sky && self.sky.is_none() {
+                problems.push(format!("sector {}: ceiling is sky, but the level doesn't have a sky texture", s));
+            }
+            for ceiling in [false, true] {
+                if sector.plane(ceiling).scale <= 0 {
+                    problems.push(format!("sector {}: texture scale {} has to be above 0", s, sector.plane(ceiling).scale));
+                }
+            }
+        }
+
+        for (w, wall) in self.walls_data.iter().enumerate() {
+            if wall.texture < 0 || wall.texture >= textures.len() as i32 {
+                problems.push(format!("wall {}: texture {} doesn't exist ({} textures loaded)", w, wall.texture_ref, textures.len()));
+            }
+            if let Some(trigger) = &wall.trigger {
+                if !self.sectors_data.iter().any(|sector| sector.mover.as_ref().is_some_and(|m| m.tag == trigger.tag)) {
+                    problems.push(format!("wall {}: trigger tag {} doesn't match any moving sector", w, trigger.tag));
+                }
+            }
+            for (kind, texture, texture_ref) in [("upper", Some(wall.upper), &wall.upper_ref),
+                                                 ("lower", Some(wall.lower), &wall.lower_ref),
+                                                 ("middle", wall.middle, &wall.middle_ref)] {
+                if let (Some(texture), Some(name)) = (texture, texture_ref) {
+                    if texture < 0 || texture >= textures.len() as i32 {
+                        problems.push(format!("wall {}: {} texture {} doesn't exist ({} textures loaded)", w, kind, name, textures.len()));
+                    }
+                }
+            }
+        }
+
+        for (t, thing) in self.things.iter().enumerate() {
+            if thing.sprite < 0 || thing.sprite >= textures.len() as i32 {
+                problems.push(format!("thing {}: sprite {} doesn't exist ({} textures loaded)", t, thing.sprite_ref, textures.len()));
+            }
+            if thing.scale <= 0.0 {
+                problems.push(format!("thing {}: scale {} has to be above 0", t, thing.scale));
+            }
+            if let Some(s) = thing.sector {
+                match usize::try_from(s).ok().and_then(|s| self.sectors_data.get(s)) {
+                    None => problems.push(format!("thing {}: sector {} doesn't exist", t, s)),
+
+                    // Sectors with broken walls are reported above
+                    Some(sector) if sector.ws < 0 || sector.we > walls || sector.ws >= sector.we => {},
+                    Some(sector) if !sector.contains(&self.walls_data, thing.x, thing.y) => {
+                        problems.push(format!("thing {}: ({}, {}) is not inside sector {}", t, thing.x, thing.y, s));
+                    },
+                    Some(_) => {}
+                }
+            }
+        }
+
+        // Texture used as a frame twice wouldn't know which frame comes after it
+        let mut animated: Vec<i32> = Vec::new();
+        for (a, animation) in self.animations.iter().enumerate() {
+            if animation.frames.is_empty() {
+                problems.push(format!("animation {}: has no frames", a));
+            }
+            if animation.rate <= 0.0 {
+                problems.push(format!("animation {}: rate {} has to be above 0", a, animation.rate));
+            }
+            for (frame, name) in animation.frames.iter().zip(&animation.frames_ref) {
+                if *frame < 0 || *frame >= textures.len() as i32 {
+                    problems.push(format!("animation {}: frame {} doesn't exist ({} textures loaded)", a, name, textures.len()));
+                } else if animated.contains(frame) {
+                    problems.push(format!("animation {}: frame {} is already a frame of an animation", a, name));
+                } else {
+                    animated.push(*frame);
+                }
+            }
+        }
+
+        if let (Some(colormap), Some(name)) = (self.colormap, &self.colormap_ref) {
+            if colormap < 0 || colormap >= textures.len() as i32 {
+                problems.push(format!("colormap {} doesn't exist ({} textures loaded)", name, textures.len()));
+            } else if textures[colormap as usize].width != 256 {
+                problems.push(format!("colormap {} has to be 256 pixels wide, one column for every colour value", name));
+            }
+        }
+        if let (Some(sky), Some(name)) = (self.sky, &self.sky_ref) {
+            if sky < 0 || sky >= textures.len() as i32 {
+                problems.push(format!("sky {} doesn't exist ({} textures loaded)", name, textures.len()));
+            }
+        }
+        if let Some(fog) = self.fog.as_ref().filter(|f| f.distance <= 0.0) {
+            problems.push(format!("fog distance {} has to be above 0", fog.distance));
+        }
+
+        problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Level;
+    use super::super::{Player, Texture};
+    use super::super::player::HEIGHT;
+    use super::super::fixtures;
+    use crate::resources::Resources;
+
+    use serde_json::{json, Value};
+    use sfml::system::Vector3f;
+
+    use std::path::Path;
+
+
+    fn two_rooms() -> Value {
+        fixtures::two_rooms(0, 64)
+    }
+
+    fn problems(json: Value) -> Vec<String> {
+        let textures = vec![Texture {width: 1, height: 1, name: "T1".to_string(), data: vec![0; 3]}];
+        let mut level: Level = serde_json::from_value(json).unwrap();
+        level.resolve_textures(&textures);
+        level.validate(&textures)
+    }
+
+    fn assert_one(json: Value, expected: &str) {
+        let problems = problems(json);
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].contains(expected), "{:?}", problems);
+    }
+
+
+    #[test]
+    fn valid() {
+        assert_eq!(problems(two_rooms()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn count_mismatch() {
+        let mut level = two_rooms();
+        level["sectors"] = json!(3);
+        assert_one(level, "`sectors` is 3, but there are 2 sectors");
+
+        let mut level = two_rooms();
+        level["walls"] = json!(7);
+        assert_one(level, "`walls` is 7, but there are 8 walls");
+    }
+
+    #[test]
+    fn wall_range() {
+        let mut level = two_rooms();
+        level["sectors_data"][1]["we"] = json!(9);
+        assert_one(level, "sector 1: walls 4..9 are not a valid range");
+
+        let mut level = two_rooms();
+        level["sectors_data"][0]["ws"] = json!(-1);
+        assert_one(level, "sector 0: walls -1..4 are not a valid range");
+
+        let mut level = two_rooms();
+        level["sectors_data"][0]["we"] = json!(0);
+        assert_one(level, "sector 0: walls 0..0 are not a valid range");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unclosed_loop() {
+        let mut level = two_rooms();
+        level["walls_data"][3]["x2"] = json!(8);
+        assert_one(level, "sector 0: walls don't form a closed loop, wall 3 ends at (8, 0) but wall 0 starts at (0, 0)");
+    }
+
+    #[test]
+    fn no_room_inside() {
+        let mut level = two_rooms();
+        level["sectors_data"][1]["z1"] = json!(64);
+        assert_one(level, "sector 1: bottom z1 (64) has to be below top z2 (64)");
+
+        let mut level = two_rooms();
+        level["sectors_data"][0]["z1"] = json!(80);
+        assert_one(level, "sector 0: bottom z1 (80) has to be below top z2 (64)");
+    }
+
+    #[test]
+    fn missing_texture() {
+        let mut level = two_rooms();
+        level["walls_data"][5]["texture"] = json!("NOPE");
+        assert_one(level, "wall 5: texture `NOPE` doesn't exist (1 textures loaded)");
+
+        let mut level = two_rooms();
+        level["sectors_data"][0]["ceiling_texture"] = json!(3);
+        assert_one(level, "sector 0: ceiling_texture 3 doesn't exist");
+    }
+
+    #[test]
+    fn bad_portal() {
+        let mut level = two_rooms();
+        level["walls_data"][2]["portal"] = json!(2);
+        assert_one(level, "sector 0: wall 2 is a portal to sector 2, which isn't another sector");
+
+        let mut level = two_rooms();
+        level["walls_data"][4]["portal"] = json!(1);
+        assert_one(level, "sector 1: wall 4 is a portal to sector 1, which isn't another sector");
+    }
+
+    #[test]
+    fn every_problem_reported() {
+        let mut level = two_rooms();
+        level["walls"] = json!(9);
+        level["walls_data"][0]["texture"] = json!("NOPE");
+        level["walls_data"][4]["portal"] = json!(-1);
+        level["sectors_data"][1]["z2"] = json!(-8);
+
+        let problems = problems(level);
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        for expected in ["`walls` is 9", "wall 4 is a portal to sector -1", "bottom z1 (0) has to be below top z2 (-8)", "wall 0: texture `NOPE`"] {
+            assert!(problems.iter().any(|p| p.contains(expected)), "{} not in {:?}", expected, problems);
+        }
+    }
+
+    #[test]
+    fn moving_sectors() {
+        // Door on the left of the hall and a lift in the closet behind it
+        let resources = Resources::new(Path::new("src"), &[]).unwrap();
+        let textures = Texture::texture_loader(&resources, Path::new("textures")).unwrap();
+        let level = &mut Level::level_loader(&resources, Path::new("levels/movers.lvl"), &textures).unwrap();
+        let (door, closet) = (3, 4);
+        let run = |level: &mut Level, seconds: f32, player: &Player| {
+            for _ in 0..(seconds * 30.0) as i32 {level.update(1.0 / 30.0, player.pos.x, player.pos.y, player.radius);}
+        };
+
+        let mut player = Player::new();
+        player.pos = Vector3f::new(16.0, 64.0, 24.0);
+        player.yaw = 270.0;
+        player.update_sector(level);
+
+        let w = player.facing_wall(level).expect("door is right in front");
+        level.trigger(w, false);
+        run(level, 1.0, &player);
+        assert_eq!(level.sectors_data[door].z2, 48);
+
+        // Doesn't close on the player standing in the doorway
+        player.pos = Vector3f::new(-4.0, 64.0, 24.0);
+        player.update_sector(level);
+        assert_eq!(player.sector, Some(door));
+        run(level, 5.0, &player);
+        assert!(level.sectors_data[door].z2 >= HEIGHT, "door closed down to {}", level.sectors_data[door].z2);
+
+        // Nor on the player in the hall, but close enough to stick into the doorway
+        player.pos = Vector3f::new(4.0, 64.0, 24.0);
+        player.update_sector(level);
+        assert_eq!(player.sector, Some(0));
+        run(level, 5.0, &player);
+        assert!(level.sectors_data[door].z2 >= HEIGHT, "door closed down to {}", level.sectors_data[door].z2);
+
+        // Walking into the closet takes its floor down
+        for w in level.walls_crossed((-4.0, 64.0), (-12.0, 64.0)) {level.trigger(w, true);}
+        player.pos = Vector3f::new(16.0, 64.0, 24.0);
+        player.update_sector(level);
+        run(level, 1.5, &player);
+        assert_eq!(level.sectors_data[closet].z1, -32);
+
+        // Both go back once the player is out of the way
+        run(level, 5.0, &player);
+        assert_eq!((level.sectors_data[door].z2, level.sectors_data[closet].z1), (0, 0));
+    }
+}
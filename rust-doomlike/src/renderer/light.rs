@@ -0,0 +1,85 @@
+use serde::Deserialize;
+
+use super::Texture;
+
+
+// Light levels go from 0 (black) to 255 (texture as it is)
+pub const FULL_LIGHT: u8 = 255;
+
+// Everything fades into `color` with distance, it's all `color` from `distance` on
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Fog {
+    pub color: [u8; 3],
+    pub distance: f32,
+}
+impl Default for Fog {
+    fn default() -> Self {
+        Fog {color: [0, 0, 0], distance: 1024.0}
+    }
+}
+
+// Light level of a wall, darker than its sector by `shade/2` but never below black
+pub fn wall_light(sector: u8, shade: u8) -> u8 {
+    sector.saturating_sub(shade / 2)
+}
+
+// Colour of texel `color` in light level `light`, `depth` away from the camera.
+// With a colormap its rows are light levels (full light at the top, darkest at the bottom)
+// and 256 columns map every channel value to the lit one, like COLORMAP lump in Doom.
+pub fn lit(color: [u8; 3], light: u8, depth: f32, fog: Option<&Fog>, colormap: Option<&Texture>) -> [u8; 3] {
+    let lit = match colormap {
+        Some(map) => {
+            let v = light as i32 * (map.height - 1) / FULL_LIGHT as i32;
+            [map.texel(color[0] as i32, v)[0], map.texel(color[1] as i32, v)[1], map.texel(color[2] as i32, v)[2]]
+        },
+        None => color.map(|c| (c as u16 * light as u16 / FULL_LIGHT as u16) as u8),
+    };
+
+    let Some(fog) = fog else {return lit;};
+    let amount = (depth / fog.distance).clamp(0.0, 1.0);
+    std::array::from_fn(|i| (lit[i] as f32 + (fog.color[i] as f32 - lit[i] as f32) * amount) as u8)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{lit, wall_light, Fog, FULL_LIGHT};
+    use super::super::Texture;
+
+
+    #[test]
+    fn dark_and_shaded() {
+        assert_eq!(wall_light(200, 100), 150);
+        assert_eq!(wall_light(40, 255), 0);
+        assert_eq!(lit([3, 2, 1], wall_light(40, 255), 0.0, None, None), [0, 0, 0]);
+        assert_eq!(lit([255, 255, 255], FULL_LIGHT, 0.0, None, None), [255, 255, 255]);
+        assert_eq!(lit([200, 100, 50], 128, 0.0, None, None), [100, 50, 25]);
+    }
+
+    #[test]
+    fn fog() {
+        let fog = Fog {color: [100, 200, 0], distance: 512.0};
+        let color = [200, 0, 100];
+        assert_eq!(lit(color, FULL_LIGHT, 0.0, Some(&fog), None), color);
+        assert_eq!(lit(color, FULL_LIGHT, 256.0, Some(&fog), None), [150, 100, 50]);
+        assert_eq!(lit(color, FULL_LIGHT, 512.0, Some(&fog), None), fog.color);
+        assert_eq!(lit(color, FULL_LIGHT, 5000.0, Some(&fog), None), fog.color);
+
+        // Fog is on top of the light level
+        assert_eq!(lit(color, 0, 256.0, Some(&fog), None), [50, 100, 0]);
+        assert_eq!(lit(color, FULL_LIGHT, 5000.0, None, None), color);
+    }
+
+    #[test]
+    fn colormap() {
+        // 4 rows, every one of them maps a channel value to (value + row from the bottom) % 256
+        let data = (0..4).rev().flat_map(|row| (0..256).flat_map(move |c| [((c + row) % 256) as u8; 3])).collect();
+        let map = Texture {width: 256, height: 4, name: "colormap".to_string(), data};
+
+        assert_eq!(lit([10, 20, 255], FULL_LIGHT, 0.0, None, Some(&map)), [13, 23, 2]);
+        assert_eq!(lit([10, 20, 255], 0, 0.0, None, Some(&map)), [10, 20, 255]);
+        assert_eq!(lit([10, 20, 255], 170, 0.0, None, Some(&map)), [12, 22, 1]);
+        assert_eq!(lit([10, 20, 255], 169, 0.0, None, Some(&map)), [11, 21, 0]);
+    }
+}
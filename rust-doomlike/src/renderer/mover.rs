@@ -0,0 +1,172 @@
+use serde::Deserialize;
+
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Door,       // Ceiling goes up to `height` and back down
+    Lift,       // Floor goes down to `height` and back up
+    Crusher,    // Ceiling goes down to `height` and back up, over and over
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum State {
+    #[default]
+    Idle,
+    Going,          // To `height`
+    Waiting(f32),   // Seconds left
+    Returning,      // Back to where it was in the level
+}
+
+// Sector that changes its floor or ceiling height once a trigger with its `tag` goes off
+#[derive(Deserialize, Debug)]
+pub struct Mover {
+    pub tag: i32,
+    pub kind: Kind,
+
+    // Where the floor (lifts) or the ceiling (doors, crushers) goes
+    pub height: i32,
+
+    // World units per second
+    pub speed: f32,
+    // Seconds it stays at `height` before going back
+    #[serde(default = "default_wait")]
+    pub wait: f32,
+
+    #[serde(skip)]
+    state: State,
+    #[serde(skip)]
+    rest: f32,      // Height from the level
+    #[serde(skip)]
+    z: f32,         // Height now, sector has it rounded
+}
+fn default_wait() -> f32 {3.0}
+
+impl Mover {
+    // Start from height `z`, nothing happens while it's moving already
+    pub fn activate(&mut self, z: i32) {
+        if self.state != State::Idle {return;}
+        self.state = State::Going;
+        self.rest = z as f32;
+        self.z = z as f32;
+    }
+
+    // Move by `time` seconds, gives the new height or None when standing still.
+    // Ceilings don't go below `lowest` - doors open again, crushers wait until there's room.
+    pub fn advance(&mut self, time: f32, lowest: i32) -> Option<i32> {
+        let target = match self.state {
+            State::Idle => return None,
+            State::Waiting(left) => {
+                self.state = if left > time {State::Waiting(left - time)} else {State::Returning};
+                return None;
+            },
+            State::Going => self.height as f32,
+            State::Returning => self.rest,
+        };
+
+        let step = self.speed * time;
+        let mut z = if (target - self.z).abs() <= step {target} else {self.z + step.copysign(target - self.z)};
+
+        if self.kind != Kind::Lift && z < self.z && z < lowest as f32 {
+            if self.kind == Kind::Door {self.state = State::Going;}
+            z = self.z;
+        }
+        self.z = z;
+
+        if z == target {
+            self.state = match self.state {
+                State::Going => State::Waiting(self.wait),
+                _ if self.kind == Kind::Crusher => State::Going,
+                _ => State::Idle,
+            };
+        }
+        Some(z.round() as i32)
+    }
+}
+
+
+// Wall that starts every sector with a mover with the same `tag`,
+// when the player uses it or, with `walk`, walks over it
+#[derive(Deserialize, Debug)]
+pub struct Trigger {
+    pub tag: i32,
+    #[serde(default)]
+    pub walk: bool,
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::State;
+    use super::super::fixtures::mover;
+
+
+    #[test]
+    fn door_opens_again_when_blocked() {
+        let mut door = mover("Door", 48, 64.0, 1.0);
+        door.activate(0);
+        assert_eq!(door.advance(0.5, 0), Some(32));
+        assert_eq!(door.advance(0.5, 0), Some(48));
+        assert_eq!(door.state, State::Waiting(1.0));
+
+        assert_eq!(door.advance(1.0, 32), None);
+        assert_eq!(door.advance(0.25, 32), Some(32));
+
+        // Next step would go below the player's head
+        assert_eq!(door.advance(0.25, 32), Some(32));
+        assert_eq!(door.state, State::Going);
+        assert_eq!(door.advance(0.25, 32), Some(48));
+        assert_eq!(door.state, State::Waiting(1.0));
+    }
+
+    #[test]
+    fn crusher_cycles() {
+        let mut crusher = mover("Crusher", 8, 32.0, 0.5);
+        crusher.activate(64);
+        for _ in 0..7 {crusher.advance(0.25, 0);}
+        assert_eq!(crusher.z, 8.0);
+        assert_eq!(crusher.state, State::Waiting(0.5));
+
+        crusher.advance(0.5, 0);
+        for _ in 0..7 {crusher.advance(0.25, 0);}
+        assert_eq!(crusher.z, 64.0);
+
+        // Goes down again without a trigger, but waits while something is in the way
+        assert_eq!(crusher.state, State::Going);
+        assert_eq!(crusher.advance(0.5, 56), Some(64));
+        assert_eq!(crusher.advance(0.25, 56), Some(56));
+        assert_eq!(crusher.advance(0.25, 56), Some(56));
+        assert_eq!(crusher.state, State::Going);
+        assert_eq!(crusher.advance(0.25, 0), Some(48));
+    }
+
+    #[test]
+    fn wait_runs_out() {
+        let mut lift = mover("Lift", -32, 32.0, 2.0);
+        lift.activate(0);
+        assert_eq!(lift.advance(1.0, 0), Some(-32));
+
+        assert_eq!(lift.advance(1.5, 0), None);
+        assert!(matches!(lift.state, State::Waiting(left) if (left - 0.5).abs() < 1e-6));
+        assert_eq!(lift.advance(0.4, 0), None);
+        assert!(matches!(lift.state, State::Waiting(_)));
+        assert_eq!(lift.advance(0.2, 0), None);
+        assert_eq!(lift.state, State::Returning);
+
+        assert_eq!(lift.advance(1.0, 0), Some(0));
+        assert_eq!(lift.state, State::Idle);
+        assert_eq!(lift.advance(1.0, 0), None);
+
+        // Can be started again once it's back
+        lift.activate(0);
+        assert_eq!(lift.advance(0.5, 0), Some(-16));
+    }
+
+    #[test]
+    fn activate_while_moving() {
+        let mut door = mover("Door", 48, 64.0, 1.0);
+        door.activate(0);
+        door.advance(0.5, 0);
+        door.activate(32);
+        assert_eq!((door.rest, door.state), (0.0, State::Going));
+    }
+}
@@ -1,81 +1,288 @@
-use sfml::{
-    system::{Vector3i},
-    window::{Key}
-};
+use sfml::system::Vector3f;
 
+use super::Level;
+use crate::input::Action;
 
+
+// How high above the floor camera is when standing in a sector
+const EYE_HEIGHT: f32 = 24.0;
+
+// Highest step player can walk up and the room it needs between floor and ceiling
+const MAX_STEP: i32 = 16;
+pub const HEIGHT: i32 = 32;
+
+const RADIUS: f32 = 8.0;
+
+// How far in front of the player walls can be used
+const USE_RANGE: f32 = 32.0;
+
+// Per second
+const MOVE_SPEED: f32 = 120.0;      // Walking and strafing
+const FLY_SPEED: f32 = 120.0;       // Up and down with Q/E
+const TURN_SPEED: f32 = 120.0;      // Degrees
+const LOOK_SPEED: f32 = 60.0;       // Looking up and down, degrees
+
+// Renderer moves the horizon instead of turning the camera up and down,
+// which looks wrong much further than this
+const MAX_PITCH: f32 = 30.0;
+
+
+#[derive(Clone)]
 pub struct Player {
-    pub pos: Vector3i,
-    pub angle: i32,
-    pub look_up_down: i32,
+    pub pos: Vector3f,
+
+    // Degrees, 0 looks along Y axis, turning left goes up to 360
+    pub yaw: f32,
+    // Degrees of looking up and down, negative is up, between -MAX_PITCH and MAX_PITCH
+    pub pitch: f32,
 
-    pub cos: [f32; 360],
-    pub sin: [f32; 360],
+    // Sector player stands in, None outside of every sector (levels made of blocks),
+    // there's no floor to stand on then and Q/E fly freely
+    pub sector: Option<usize>,
+
+    // Closest player can get to a wall
+    pub radius: f32,
 }
 
 impl Player {
     pub fn new() -> Self {
-        // Stored sine and cosine values 
-        let mut cos_buff: [f32; 360] = [0.0; 360];
-        let mut sin_buff: [f32; 360] = [0.0; 360];
-        for x in 0..360 {
-            // Converting to radians
-            cos_buff[x] = f32::cos(f32::to_radians(x as f32));
-            sin_buff[x] = f32::sin(f32::to_radians(x as f32));
-        }
-
         Player {
-            pos: Vector3i::new(-37, -190, 5),   // X - left/right   Y - close/far   Z - up/down
-            angle: 25,
-            look_up_down: 0,
-            cos: cos_buff,
-            sin: sin_buff,
+            pos: Vector3f::new(-37.0, -190.0, 5.0),   // X - left/right   Y - close/far   Z - up/down
+            yaw: 25.0,
+            pitch: 0.0,
+            sector: None,
+            radius: RADIUS,
         }
     }
 
+    // Sine and cosine of `yaw`
+    pub fn direction(&self) -> (f32, f32) {
+        self.yaw.to_radians().sin_cos()
+    }
+
 
-    pub fn advance(&mut self, key: Key, velocity: f32) -> () {
-        // I can do `self.angle as usize` as well but I'm trying to be carefull
-        let delta_x = (self.sin[usize::try_from(self.angle).unwrap()] * velocity) as i32;
-        let delta_y = (self.cos[usize::try_from(self.angle).unwrap()] * velocity) as i32;
+    // Do `action` for `time` seconds, called once per simulation tick
+    pub fn advance(&mut self, action: Action, time: f32, level: &Level) -> () {
+        let (sin, cos) = self.direction();
+        let delta_x = sin * MOVE_SPEED * time;
+        let delta_y = cos * MOVE_SPEED * time;
 
-        match key {
+        match action {
             // Move forward/backward and rotate
-            Key::W => {
-                self.pos.x = self.pos.x + delta_x as i32;
-                self.pos.y = self.pos.y + delta_y as i32;
-            },
-            Key::S => {
-                self.pos.x = self.pos.x - delta_x as i32;
-                self.pos.y = self.pos.y - delta_y as i32;
-            },
-            Key::A => {
-                self.angle = self.angle + 4;
-                if self.angle > 359 {self.angle = self.angle - 360;}
-            },
-            Key::D => {
-                self.angle = self.angle - 4;
-                if self.angle <   0 {self.angle = self.angle + 360;}
-            },
+            Action::MoveForward  => self.walk( delta_x,  delta_y, level),
+            Action::MoveBackward => self.walk(-delta_x, -delta_y, level),
+            Action::TurnLeft     => self.turn( TURN_SPEED * time, 0.0),
+            Action::TurnRight    => self.turn(-TURN_SPEED * time, 0.0),
 
             // Move up and down in Z axis
-            Key::E  => self.pos.z = self.pos.z - 4,
-            Key::Q  => self.pos.z = self.pos.z + 4,
+            Action::FlyDown => self.pos.z -= FLY_SPEED * time,
+            Action::FlyUp   => self.pos.z += FLY_SPEED * time,
 
-            Key::Down   => self.look_up_down = self.look_up_down + 1,
-            Key::Up => self.look_up_down = self.look_up_down - 1,
+            Action::LookDown => self.turn(0.0,  LOOK_SPEED * time),
+            Action::LookUp   => self.turn(0.0, -LOOK_SPEED * time),
             
             // Strafe right/left
-            Key::Left => {
-                self.pos.x = self.pos.x + delta_y as i32;
-                self.pos.y = self.pos.y - delta_x as i32;
-            },
-            Key::Right  => {
-                self.pos.x = self.pos.x - delta_y as i32;
-                self.pos.y = self.pos.y + delta_x as i32;
-            },
+            Action::StrafeLeft  => self.walk( delta_y, -delta_x, level),
+            Action::StrafeRight => self.walk(-delta_y,  delta_x, level),
 
             _ => return
         }
     }
-}
\ No newline at end of file
+
+    // Turn by `yaw` degrees and look up or down by `pitch` degrees, also used by mouse look
+    pub fn turn(&mut self, yaw: f32, pitch: f32) {
+        self.yaw = (self.yaw + yaw).rem_euclid(360.0);
+        self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    // Move by (dx, dy), sliding along walls instead of going through them
+    fn walk(&mut self, dx: f32, dy: f32, level: &Level) {
+        let (mut x, mut y) = (self.pos.x + dx, self.pos.y + dy);
+        let radius = self.radius;
+
+        // Pushing out of one wall can push into another one in a corner, few rounds sort it out
+        for _ in 0..4 {
+            for (s, sector) in level.sectors_data.iter().enumerate() {
+                for w in sector.ws as usize .. sector.we as usize {
+                    if !self.blocked_by(level, s, w) {continue;}
+
+                    let wall = &level.walls_data[w];
+                    let (ax, ay) = (wall.x1 as f32, wall.y1 as f32);
+                    let (bx, by) = (wall.x2 as f32, wall.y2 as f32);
+                    let length = (bx-ax)*(bx-ax) + (by-ay)*(by-ay);
+                    if length == 0.0 {continue;}
+
+                    let (cx, cy) = wall.closest(x, y);
+                    let distance = f32::sqrt((x-cx)*(x-cx) + (y-cy)*(y-cy));
+                    if distance >= radius {continue;}
+
+                    // Push out along the line from the wall, or back to the side player came from if standing right on it
+                    let (mut nx, mut ny) = if distance > 0.0 {((x-cx)/distance, (y-cy)/distance)} else {((ay-by)/length.sqrt(), (bx-ax)/length.sqrt())};
+                    if distance == 0.0 && nx*(self.pos.x - ax) + ny*(self.pos.y - ay) < 0.0 {(nx, ny) = (-nx, -ny);}
+
+                    x += nx * (radius - distance);
+                    y += ny * (radius - distance);
+                }
+            }
+        }
+
+        self.pos.x = x;
+        self.pos.y = y;
+    }
+
+    // Wall right in front of the player that using would reach. Portals without a trigger don't stop
+    // the reach, like in Doom a switch can be pressed through an open doorway.
+    pub fn facing_wall(&self, level: &Level) -> Option<usize> {
+        let (sin, cos) = self.direction();
+        let (from, to) = ((self.pos.x, self.pos.y), (self.pos.x + sin * USE_RANGE, self.pos.y + cos * USE_RANGE));
+
+        level.walls_data.iter().enumerate()
+            .filter(|(_, wall)| wall.portal.is_none() || wall.trigger.is_some())
+            .filter_map(|(w, wall)| wall.crossed_by(from, to).map(|along| (w, along)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(w, _)| w)
+    }
+
+    // Solid walls stop the player at the height of their sector. Portals from the sector
+    // player is in only if the next one is too high to step on or there's no room to fit in.
+    fn blocked_by(&self, level: &Level, s: usize, w: usize) -> bool {
+        let sector = &level.sectors_data[s];
+        match level.walls_data[w].portal {
+            None => sector.z1 as f32 <= self.pos.z && self.pos.z <= sector.z2 as f32,
+
+            // The same wall seen from the other side, its twin in player's sector decides
+            Some(_) if self.sector != Some(s) => false,
+
+            Some(n) => {
+                let next = &level.sectors_data[n as usize];
+                let opening = next.z2.min(sector.z2) - next.z1.max(sector.z1);
+                next.z1 - sector.z1 > MAX_STEP || opening < HEIGHT
+            }
+        }
+    }
+
+    // Call after every move. Inside a sector camera stays at eye height above its floor,
+    // but below its ceiling. Sectors above or below the camera (like tops of blocks) don't count.
+    pub fn update_sector(&mut self, level: &Level) {
+        self.sector = self.find_sector(level);
+
+        if let Some(s) = self.sector {
+            let sector = &level.sectors_data[s];
+            self.pos.z = (sector.z1 as f32 + EYE_HEIGHT).min(sector.z2 as f32 - 1.0);
+        }
+    }
+
+    fn find_sector(&self, level: &Level) -> Option<usize> {
+        level.sector_at(self.pos.x, self.pos.y).filter(|s| {
+            let sector = &level.sectors_data[*s];
+            sector.z1 as f32 <= self.pos.z && self.pos.z <= sector.z2 as f32
+        })
+    }
+
+    // Pose to draw between the previous tick and this one, `alpha` goes from 0 (previous) to 1 (this one)
+    pub fn interpolate(&self, previous: &Player, alpha: f32, level: &Level) -> Player {
+        // Shorter way around, e.g. 350 to 10 goes over 0
+        let turn = (self.yaw - previous.yaw + 540.0).rem_euclid(360.0) - 180.0;
+
+        let mut pose = self.clone();
+        pose.pos = previous.pos + (self.pos - previous.pos) * alpha;
+        pose.yaw = (previous.yaw + turn * alpha).rem_euclid(360.0);
+        pose.pitch = previous.pitch + (self.pitch - previous.pitch) * alpha;
+        pose.sector = pose.find_sector(level);
+        pose
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Player, Level};
+    use super::super::fixtures;
+
+    use sfml::system::Vector3f;
+
+
+    fn two_rooms(floor: i32, ceiling: i32) -> Level {
+        serde_json::from_value(fixtures::two_rooms(floor, ceiling)).unwrap()
+    }
+
+    fn player_at(x: f32, y: f32, level: &Level) -> Player {
+        let mut player = Player::new();
+        player.pos = Vector3f::new(x, y, 0.0);
+        player.update_sector(level);
+        assert_eq!(player.sector, Some(0));
+        player
+    }
+
+    // A few units at a time like ticks do, one big move would go right through a wall
+    fn walk(player: &mut Player, dx: f32, dy: f32, level: &Level) {
+        for _ in 0..20 {player.walk(dx / 20.0, dy / 20.0, level);}
+    }
+
+    fn assert_at(player: &Player, x: f32, y: f32) {
+        assert!((player.pos.x - x).abs() < 0.01 && (player.pos.y - y).abs() < 0.01,
+                "player at ({}, {}) instead of ({}, {})", player.pos.x, player.pos.y, x, y);
+    }
+
+
+    #[test]
+    fn stops_at_wall() {
+        let level = two_rooms(0, 64);
+        let mut player = player_at(32.0, 32.0, &level);
+        walk(&mut player, -40.0, 0.0, &level);
+        assert_at(&player, player.radius, 32.0);
+
+        // Into the corner
+        walk(&mut player, 0.0, -40.0, &level);
+        assert_at(&player, player.radius, player.radius);
+    }
+
+    #[test]
+    fn slides_along_wall() {
+        let level = two_rooms(0, 64);
+        let mut player = player_at(32.0, 32.0, &level);
+        walk(&mut player, -40.0, 10.0, &level);
+        assert_at(&player, player.radius, 42.0);
+
+        walk(&mut player, 0.0, -20.0, &level);
+        assert_at(&player, player.radius, 22.0);
+    }
+
+    #[test]
+    fn steps() {
+        let level = two_rooms(16, 64);
+        let mut player = player_at(32.0, 32.0, &level);
+        walk(&mut player, 40.0, 0.0, &level);
+        assert_at(&player, 72.0, 32.0);
+
+        let level = two_rooms(17, 64);
+        let mut player = player_at(32.0, 32.0, &level);
+        walk(&mut player, 40.0, 0.0, &level);
+        assert_at(&player, 64.0 - player.radius, 32.0);
+
+        // Going down any height is fine
+        let level = two_rooms(-100, 64);
+        let mut player = player_at(32.0, 32.0, &level);
+        walk(&mut player, 40.0, 0.0, &level);
+        assert_at(&player, 72.0, 32.0);
+    }
+
+    #[test]
+    fn clearance() {
+        let level = two_rooms(0, 32);
+        let mut player = player_at(32.0, 32.0, &level);
+        walk(&mut player, 40.0, 0.0, &level);
+        assert_at(&player, 72.0, 32.0);
+
+        let level = two_rooms(0, 31);
+        let mut player = player_at(32.0, 32.0, &level);
+        walk(&mut player, 40.0, 0.0, &level);
+        assert_at(&player, 64.0 - player.radius, 32.0);
+
+        // Step up fits, but leaves too little room under the ceiling
+        let level = two_rooms(16, 40);
+        let mut player = player_at(32.0, 32.0, &level);
+        walk(&mut player, 40.0, 0.0, &level);
+        assert_at(&player, 64.0 - player.radius, 32.0);
+    }
+}
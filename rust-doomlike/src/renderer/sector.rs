@@ -1,5 +1,21 @@
 use serde::Deserialize;
 
+use super::{Texture, TextureRef, Wall};
+use super::light::FULL_LIGHT;
+use super::mover::Mover;
+
+// Scale of floors and ceilings that don't have one
+const DEFAULT_SCALE: i32 = 4;
+
+
+// What `Renderer::floor` needs to know about the floor or the ceiling
+pub struct Plane {
+    pub texture: i32,
+    pub scale: i32,
+    pub offset: [f32; 2],
+    pub scroll: [f32; 2],
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Sector {
     // Wall start and end
@@ -14,9 +30,102 @@ pub struct Sector {
     pub surf_arr: Vec<i32>,
     pub surface: i32,
     
-    pub surface_texture: i32,
-    pub texture_scale: i32,
+    // Floor (z1) and ceiling (z2). Old levels have one `surface_texture` and `texture_scale`
+    // for both, those are used for whatever is missing.
+    #[serde(default, rename = "floor_texture")]
+    floor_texture_ref: Option<TextureRef>,
+    #[serde(default, rename = "ceiling_texture")]
+    ceiling_texture_ref: Option<TextureRef>,
+    #[serde(default, rename = "surface_texture")]
+    surface_texture_ref: Option<TextureRef>,
+    #[serde(skip)]
+    pub floor_texture: i32,         // Resolved, -1 if there's no such texture or none at all
+    #[serde(skip)]
+    pub ceiling_texture: i32,
+
+    // Bigger scale makes the texture smaller, offset moves it by world units along X and Y
+    #[serde(default)]
+    floor_scale: Option<i32>,
+    #[serde(default)]
+    ceiling_scale: Option<i32>,
+    #[serde(default)]
+    texture_scale: Option<i32>,
+    #[serde(default)]
+    floor_offset: [f32; 2],
+    #[serde(default)]
+    ceiling_offset: [f32; 2],
+
+    // World units per second the textures move by, along X and Y
+    #[serde(default)]
+    floor_scroll: [f32; 2],
+    #[serde(default)]
+    ceiling_scroll: [f32; 2],
+
+    // Open air, the level's sky is drawn instead of the ceiling
+    #[serde(default)]
+    pub sky: bool,
+
+    // Door, lift or crusher, changes `z1` or `z2` while it moves
+    #[serde(default)]
+    pub mover: Option<Mover>,
+
+    // Light level of everything in the sector, 0 - 255
+    #[serde(default = "full_light")]
+    pub light: u8,
 
     // Distance for drawing order
     pub dist: i32
-}
\ No newline at end of file
+}
+fn full_light() -> u8 {FULL_LIGHT}
+
+impl Sector {
+    pub fn floor_texture_ref(&self) -> Option<&TextureRef> {
+        self.floor_texture_ref.as_ref().or(self.surface_texture_ref.as_ref())
+    }
+
+    pub fn ceiling_texture_ref(&self) -> Option<&TextureRef> {
+        self.ceiling_texture_ref.as_ref().or(self.surface_texture_ref.as_ref())
+    }
+
+    pub fn resolve_textures(&mut self, textures: &[Texture]) {
+        self.floor_texture   = self.floor_texture_ref().map_or(-1, |t| t.resolve(textures));
+        self.ceiling_texture = self.ceiling_texture_ref().map_or(-1, |t| t.resolve(textures));
+    }
+
+    pub fn plane(&self, ceiling: bool) -> Plane {
+        if ceiling {
+            Plane {
+                texture: self.ceiling_texture,
+                scale: self.ceiling_scale.or(self.texture_scale).unwrap_or(DEFAULT_SCALE),
+                offset: self.ceiling_offset,
+                scroll: self.ceiling_scroll,
+            }
+        } else {
+            Plane {
+                texture: self.floor_texture,
+                scale: self.floor_scale.or(self.texture_scale).unwrap_or(DEFAULT_SCALE),
+                offset: self.floor_offset,
+                scroll: self.floor_scroll,
+            }
+        }
+    }
+
+    // Point inside the floor plan made by walls `ws..we` (even-odd rule)
+    pub fn contains(&self, walls: &[Wall], x: f32, y: f32) -> bool {
+        let mut inside = false;
+        for wall in &walls[self.ws as usize .. self.we as usize] {
+            if (wall.y1 as f32 > y) != (wall.y2 as f32 > y) {
+                let cross = wall.x1 as f32 + (y - wall.y1 as f32) * (wall.x2 - wall.x1) as f32 / (wall.y2 - wall.y1) as f32;
+                if x < cross {inside = !inside;}
+            }
+        } inside
+    }
+
+    // Circle around (x, y) is at least partly inside the floor plan
+    pub fn overlaps(&self, walls: &[Wall], x: f32, y: f32, radius: f32) -> bool {
+        self.contains(walls, x, y) || walls[self.ws as usize .. self.we as usize].iter().any(|wall| {
+            let (cx, cy) = wall.closest(x, y);
+            (x-cx)*(x-cx) + (y-cy)*(y-cy) < radius*radius
+        })
+    }
+}
@@ -0,0 +1,228 @@
+// Golden image tests for the sector renderer.
+// Every pose is rendered headless and compared against a reference PNG from `golden/`.
+// After an intentional change in the output run `UPDATE_GOLDEN=1 cargo test`
+// and check the new images before committing them.
+use super::{Renderer, Player, Framebuffer};
+use crate::resources::Resources;
+
+use sfml::system::Vector3f;
+
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+
+const DATA: &str = "src";
+const BLOCKS: &str = "levels/test.json";
+const ROOMS: &str = "levels/rooms.lvl";
+// Long block in front of a small one that sorts nearer by the average distance of its walls
+const LONG_BLOCK: &str = "levels/long_block.json";
+// The rooms in fog and lit through a colormap
+const LIGHT: &str = "levels/light.lvl";
+// The rooms with textured steps between the hall, the corridor and the far room
+const STEPS: &str = "levels/steps.lvl";
+// The rooms with animated water on the floor and a waterfall scrolling down the far wall
+const ANIMATED: &str = "levels/animated.lvl";
+// The rooms with a door on the left of the hall and a lift in the closet behind it
+const MOVERS: &str = "levels/movers.lvl";
+// The rooms with a banner in the hall and a fence in front of the far room
+const MASKED: &str = "levels/masked.lvl";
+// Blocks and rooms again with things in them
+const THINGS_BLOCKS: &str = "levels/things.json";
+const THINGS_ROOMS: &str = "levels/things.lvl";
+const TEXTURES: &str = "textures";
+const GOLDEN_DIR: &str = "src/renderer/golden";
+
+// Max difference of a single colour channel before pixel counts as changed
+const TOLERANCE: u8 = 2;
+
+
+struct Pose {
+    name: &'static str,
+    level: &'static str,
+    pos: (f32, f32, f32),
+    yaw: f32,
+    pitch: f32,
+}
+
+const POSES: [Pose; 25] = [
+    Pose {name: "front",      level: BLOCKS,  pos: (  48.0,  -80.0,  10.0), yaw:   0.0, pitch:   0.0},
+    Pose {name: "between",    level: BLOCKS,  pos: (  48.0,   48.0,  10.0), yaw:  45.0, pitch:   0.0},
+    Pose {name: "corner",     level: BLOCKS,  pos: ( -40.0,  -40.0,  10.0), yaw:  45.0, pitch:   0.0},
+    Pose {name: "look_up",    level: BLOCKS,  pos: (  48.0,  -80.0,  10.0), yaw:  10.0, pitch: -14.0},
+    Pose {name: "look_down",  level: BLOCKS,  pos: (  48.0,  -80.0,  10.0), yaw: 350.0, pitch:  14.0},
+    Pose {name: "rooftops",   level: BLOCKS,  pos: ( 140.0,   48.0,  70.0), yaw: 270.0, pitch:  10.0},
+    Pose {name: "hall",       level: ROOMS,   pos: (  64.0,   16.0,  24.0), yaw:   0.0, pitch:   0.0},
+    Pose {name: "doorway",    level: ROOMS,   pos: (  64.0,  110.0,  24.0), yaw:  10.0, pitch:   0.0},
+    Pose {name: "far_room",   level: ROOMS,   pos: (  64.0,  300.0,  24.0), yaw: 180.0, pitch:   0.0},
+    Pose {name: "hall_corner",level: ROOMS,   pos: (  16.0,   16.0,  24.0), yaw:  45.0, pitch:   7.0},
+    Pose {name: "sky",        level: ROOMS,   pos: (  64.0,  280.0,  24.0), yaw: 150.0, pitch: -25.0},
+    Pose {name: "light_hall",     level: LIGHT, pos: (64.0,  16.0, 24.0), yaw:   0.0, pitch: 0.0},
+    Pose {name: "light_far_room", level: LIGHT, pos: (64.0, 300.0, 24.0), yaw: 180.0, pitch: 0.0},
+    Pose {name: "masked_hall",    level: MASKED, pos: (64.0,  16.0, 24.0), yaw:  0.0, pitch: 0.0},
+    Pose {name: "masked_doorway", level: MASKED, pos: (64.0, 110.0, 24.0), yaw: 10.0, pitch: 0.0},
+    Pose {name: "steps_hall",     level: STEPS, pos: (64.0,  16.0, 24.0), yaw:   0.0, pitch: 0.0},
+    Pose {name: "steps_far_room", level: STEPS, pos: (64.0, 300.0, 24.0), yaw: 180.0, pitch: 0.0},
+    Pose {name: "animated_hall",     level: ANIMATED, pos: (64.0,  16.0, 24.0), yaw:   0.0, pitch: 0.0},
+    Pose {name: "animated_far_room", level: ANIMATED, pos: (64.0, 300.0, 24.0), yaw: 180.0, pitch: 0.0},
+    Pose {name: "movers_door", level: MOVERS, pos: (124.0, 64.0, 24.0), yaw: 270.0, pitch: 0.0},
+    Pose {name: "things_front",           level: THINGS_BLOCKS, pos: ( 48.0, -80.0, 10.0), yaw:   0.0, pitch: 0.0},
+    Pose {name: "things_corner",          level: THINGS_BLOCKS, pos: (-40.0, -40.0, 10.0), yaw:  45.0, pitch: 0.0},
+    Pose {name: "things_hall",            level: THINGS_ROOMS,  pos: ( 64.0,  16.0, 24.0), yaw:   0.0, pitch: 0.0},
+    Pose {name: "things_far_room",        level: THINGS_ROOMS,  pos: ( 64.0, 300.0, 24.0), yaw: 180.0, pitch: 0.0},
+    Pose {name: "things_through_portals", level: THINGS_ROOMS,  pos: ( 90.0, 260.0, 24.0), yaw: 202.0, pitch: 0.0},
+];
+
+
+fn load(pose: &Pose) -> (Renderer, Player) {
+    let resources = Resources::new(Path::new(DATA), &[]).unwrap();
+    let renderer = Renderer::new(resources, Path::new(pose.level), Path::new(TEXTURES), 200).unwrap();
+    let mut player = Player::new();
+    player.pos = Vector3f::new(pose.pos.0, pose.pos.1, pose.pos.2);
+    player.yaw = pose.yaw;
+    player.pitch = pose.pitch;
+    player.update_sector(&renderer.level);
+    (renderer, player)
+}
+
+// `time` is the game clock in seconds
+fn render(pose: &Pose, per_pixel: bool, time: f32) -> Framebuffer {
+    let (mut renderer, player) = load(pose);
+
+    let mut frame = Framebuffer::new(160, 120);
+    frame.clear(80, 100, 80);
+    frame.depth.set_per_pixel(per_pixel);
+    renderer.set_time(time);
+    renderer.draw(&player, &mut frame);
+    frame
+}
+
+fn read_png(path: &Path) -> (u32, u32, Vec<u8>) {
+    let file = File::open(path).unwrap_or_else(|e| panic!("Can't open {}: {} (run with UPDATE_GOLDEN=1 to create it)", path.display(), e));
+    let decoder = png::Decoder::new(file);
+    let mut reader = decoder.read_info().unwrap();
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).unwrap();
+
+    assert_eq!(info.color_type, png::ColorType::Rgba, "{} is not an RGBA image", path.display());
+    data.truncate(info.buffer_size());
+    (info.width, info.height, data)
+}
+
+fn write_png(path: &Path, frame: &Framebuffer) {
+    let file = File::create(path).unwrap();
+    let mut encoder = png::Encoder::new(BufWriter::new(file), frame.width, frame.height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header().unwrap().write_image_data(&frame.data).unwrap();
+}
+
+fn golden_path(pose: &Pose) -> PathBuf {
+    Path::new(GOLDEN_DIR).join([pose.name, ".png"].join(""))
+}
+
+
+#[test]
+fn golden_images() {
+    let update = env::var("UPDATE_GOLDEN").is_ok();
+    let mut failed: Vec<String> = Vec::new();
+
+    for pose in POSES.iter() {
+        let frame = render(pose, false, 0.0);
+        let path = golden_path(pose);
+
+        if update {
+            write_png(&path, &frame);
+            continue;
+        }
+
+        let (width, height, expected) = read_png(&path);
+        assert_eq!((width, height), (frame.width, frame.height), "{} has wrong size", path.display());
+
+        let changed = frame.data.chunks_exact(4).zip(expected.chunks_exact(4))
+            .filter(|(a, b)| a.iter().zip(b.iter()).any(|(x, y)| x.abs_diff(*y) > TOLERANCE))
+            .count();
+
+        if changed > 0 {
+            // Keep what we got, so it can be compared with the reference by eye
+            let actual = env::temp_dir().join(["golden_", pose.name, ".png"].join(""));
+            write_png(&actual, &frame);
+            failed.push(format!("{}: {} pixels differ, got {}", pose.name, changed, actual.display()));
+        }
+    }
+
+    assert!(failed.is_empty(), "Rendered frames don't match golden images:\n{}", failed.join("\n"));
+}
+
+
+#[test]
+fn blocks_out_of_order() {
+    let pose = Pose {name: "long_block", level: LONG_BLOCK, pos: (0.0, 0.0, 10.0), yaw: 0.0, pitch: 0.0};
+    let both = render(&pose, false, 0.0);
+
+    // Small block behind is hidden, so the frame is the same without it
+    let (mut renderer, player) = load(&pose);
+    renderer.level.sectors_data.truncate(1);
+    let mut alone = Framebuffer::new(160, 120);
+    alone.clear(80, 100, 80);
+    renderer.draw(&player, &mut alone);
+    assert!(both.data == alone.data, "small block shows in front of the long one");
+}
+
+#[test]
+fn depth_buffer() {
+    // Looking straight through both portals at the far wall of the last room
+    let hall = POSES.iter().find(|p| p.name == "hall").unwrap();
+
+    let walls = render(hall, false, 0.0);
+    assert!((walls.depth.at(80, 75) - 304.0).abs() < 1.0, "far wall is {} away", walls.depth.at(80, 75));
+    // Steps up to the corridor floor and down to its ceiling are the closest walls in the column
+    assert!((walls.depth.column(80) - 112.0).abs() < 1.0, "closest wall is {} away", walls.depth.column(80));
+    assert_eq!(walls.depth.at(80, 20), walls.depth.column(80));
+    assert_eq!(walls.depth.at(80, 95), walls.depth.column(80));
+    assert_eq!(walls.depth.at(80, 0), f32::INFINITY, "floor doesn't have depth without depth per pixel");
+
+    // Floor right under the camera is closer than the floor further away
+    let pixels = render(hall, true, 0.0);
+    assert_eq!(pixels.depth.at(80, 75), walls.depth.at(80, 75));
+    assert!(pixels.depth.at(80, 0) < pixels.depth.at(80, 10));
+    assert!(pixels.depth.at(80, 10) < pixels.depth.column(80));
+}
+
+#[test]
+fn masked_depth() {
+    // Bar of the grate in the last portal, between its bars the far wall shows
+    let hall = POSES.iter().find(|p| p.name == "masked_hall").unwrap();
+    let pixels = render(hall, true, 0.0);
+    assert!((pixels.depth.at(80, 70) - 304.0).abs() < 1.0, "far wall is {} away", pixels.depth.at(80, 70));
+    assert!(pixels.depth.at(80, 74) < pixels.depth.at(80, 70));
+}
+
+
+#[test]
+fn animation() {
+    // Water on the floor of the last room ripples without flowing, 4 frames per second
+    let far_room = POSES.iter().find(|p| p.name == "animated_far_room").unwrap();
+    let (mut renderer, player) = load(far_room);
+    let water: Vec<usize> = ["water1", "water2", "water3"].iter()
+        .map(|name| renderer.textures.iter().position(|t| t.name == *name).unwrap())
+        .collect();
+
+    let mut frame = Framebuffer::new(160, 120);
+    for (time, step) in [(0.0, 0), (0.2, 0), (0.25, 1), (0.6, 2), (0.75, 0), (1.1, 1)] {
+        renderer.set_time(time);
+        renderer.draw(&player, &mut frame);
+        for (i, texture) in water.iter().enumerate() {
+            assert_eq!(renderer.frames[*texture], water[(i + step) % 3], "frame {} at {} s", i, time);
+        }
+    }
+
+    // Floor only changes with the next frame, brick wall above the corridor stays as it is
+    let row = |frame: &Framebuffer, y: usize| frame.data[y * frame.width as usize * 4 .. (y+1) * frame.width as usize * 4].to_vec();
+    let still = render(far_room, false, 0.0);
+    assert_eq!(row(&still, 119), row(&render(far_room, false, 0.2), 119), "floor moved without changing frames");
+    let next = render(far_room, false, 0.25);
+    assert_ne!(row(&still, 119), row(&next, 119), "floor didn't change");
+    assert_eq!(row(&still, 0), row(&next, 0));
+}
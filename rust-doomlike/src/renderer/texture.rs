@@ -1,41 +1,229 @@
 use serde::{Deserialize};
+use thiserror::Error;
 
-use std::error::Error;
-use std::fs::{File, read_dir};
-use std::io::BufReader;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
 
+use crate::json::json_reason;
+use crate::resources::Resources;
+
+
+#[derive(Debug, Error)]
+pub enum TextureError {
+    #[error("{}: {source}", path.display())]
+    Io {path: PathBuf, source: io::Error},
+
+    #[error("{}:{line}:{column}: {reason}", path.display())]
+    Json {path: PathBuf, line: usize, column: usize, reason: String},
+
+    // Broken or unsupported image, wrong size of pixel data etc.
+    #[error("{}: {reason}", path.display())]
+    Invalid {path: PathBuf, reason: String},
+}
+
+
+// How level points to a texture: by its position in the texture list or by its name
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum TextureRef {
+    Index(i32),
+    Name(String),
+}
+impl TextureRef {
+    // Index into texture list, -1 if there's no such texture
+    pub fn resolve(&self, textures: &[Texture]) -> i32 {
+        match self {
+            TextureRef::Index(i) => *i,
+            TextureRef::Name(name) => textures.iter().position(|t| &t.name == name).map_or(-1, |i| i as i32),
+        }
+    }
+}
+impl fmt::Display for TextureRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextureRef::Index(i) => write!(f, "{}", i),
+            TextureRef::Name(name) => write!(f, "`{}`", name),
+        }
+    }
+}
+
+
+// Texels of this colour are see-through in sprites, PNG pixels with less than half alpha get it too
+pub const TRANSPARENT: [u8; 3] = [255, 0, 255];
+
+// Image formats we can read, in order of preference when there's more than one file with the same name
+const EXTENSIONS: [&str; 3] = ["png", "bmp", "json"];
 
 #[derive(Deserialize, Debug)]
 pub struct Texture {
-    // Wall start and end
-    pub width: i32, 
-    pub height: i32, 
+    pub width: i32,
+    pub height: i32,
 
+    // Name of the file without extension, that's how levels refer to textures
+    #[serde(skip)]
     pub name: String,
+
+    // RGB, 3 bytes per pixel, rows go top to bottom
     pub data: Vec<u8>,
 }
 impl Texture {
-    pub fn texture_loader() -> Vec<Texture> {
-        Self::data_loader().unwrap()
+    pub fn texture_loader(resources: &Resources, dir: &Path) -> Result<Vec<Texture>, TextureError> {
+        Self::data_loader(resources, dir)
     }
 
-    fn data_loader() -> Result<Vec<Texture>, Box<dyn Error>> {
+    // Every image in the directory becomes a texture. The numbered "T<n>" textures come
+    // first in number order so indices used by old levels stay the same, the rest follow by name
+    fn data_loader(resources: &Resources, dir: &Path) -> Result<Vec<Texture>, TextureError> {
+        let all_files = resources.list(dir).map_err(|source| TextureError::Io {path: resources.describe(dir), source})?;
+
+        let mut files: HashMap<String, PathBuf> = HashMap::new();
+        for file in all_files {
+            let path = dir.join(file);
+            let (Some(stem), Some(ext)) = (path.file_stem(), path.extension()) else {continue;};
+            let (stem, ext) = (stem.to_string_lossy().to_string(), ext.to_string_lossy().to_lowercase());
+
+            let Some(priority) = EXTENSIONS.iter().position(|e| *e == ext) else {continue;};
+            let better = match files.get(&stem) {
+                Some(old) => priority < EXTENSIONS.iter().position(|e| *e == Self::extension(old)).unwrap(),
+                None => true,
+            };
+            if better {files.insert(stem, path);}
+        }
+
+        let mut names: Vec<String> = files.keys().cloned().collect();
+        names.sort_by_key(|n| Self::load_order(n));
+
         let mut textures: Vec<Texture> = Vec::new();
-        let all_files = read_dir("src/textures/").unwrap();
-        let iter_to = all_files.count();
-
-        for t in 0..iter_to/2 {
-            let path = "src/textures/T";
-            let num = t.to_string();
-            let ext = ".json";
-            let full_path = [path, &num, ext].join("");
-
-            let file = File::open(full_path)?;
-            let reader = BufReader::new(file);
-            let texture: Texture = serde_json::from_reader(reader).expect("Bad JSON file");
+        for name in names {
+            let path = &resources.describe(&files[&name]);
+            let file = resources.read(&files[&name]).map_err(|source| TextureError::Io {path: path.clone(), source})?;
+
+            let mut texture = match Self::extension(path).as_str() {
+                "png" => Self::png_loader(path, &file)?,
+                "bmp" => Self::bmp_loader(path, &file)?,
+                _     => Self::json_loader(path, &file)?,
+            };
+            texture.name = name;
+
+            let (w, h) = (texture.width, texture.height);
+            if w < 1 || h < 1 {
+                return Err(TextureError::Invalid {path: path.clone(), reason: format!("size {}x{} is not valid", w, h)});
+            }
+            let Some(size) = (w as usize).checked_mul(h as usize).and_then(|n| n.checked_mul(3)) else {
+                return Err(TextureError::Invalid {path: path.clone(), reason: format!("size {}x{} is too big", w, h)});
+            };
+            if texture.data.len() != size {
+                return Err(TextureError::Invalid {path: path.clone(), reason: format!("{}x{} texture needs {} bytes of data, found {}", w, h, size, texture.data.len())});
+            }
             textures.push(texture);
         }
 
         Ok(textures)
     }
-}
\ No newline at end of file
+
+    // Colour at column `u`, row `v` counted from the bottom, both wrap around
+    pub fn texel(&self, u: i32, v: i32) -> [u8; 3] {
+        let row = self.height - v.rem_euclid(self.height) - 1;
+        let p = ((row * self.width + u.rem_euclid(self.width)) * 3) as usize;
+        [self.data[p], self.data[p+1], self.data[p+2]]
+    }
+
+    fn extension(path: &Path) -> String {
+        path.extension().map_or(String::new(), |e| e.to_string_lossy().to_lowercase())
+    }
+
+    // Legacy "T<n>" names before everything else, then text before the trailing number and the number itself
+    fn load_order(name: &str) -> (bool, String, u64, String) {
+        let text = name.trim_end_matches(|c: char| c.is_ascii_digit());
+        let number = name[text.len()..].parse().unwrap_or(0);
+        let legacy = text == "T" && text.len() < name.len();
+        (!legacy, text.to_string(), number, name.to_string())
+    }
+
+    fn invalid(path: &Path, reason: &str) -> TextureError {
+        TextureError::Invalid {path: path.to_path_buf(), reason: reason.to_string()}
+    }
+
+
+    // Old format: {"width": 16, "height": 16, "data": [r, g, b, ...]}
+    fn json_loader(path: &Path, json: &[u8]) -> Result<Texture, TextureError> {
+        serde_json::from_slice(json).map_err(|e| TextureError::Json {
+            path: path.to_path_buf(),
+            line: e.line(),
+            column: e.column(),
+            reason: json_reason(&e),
+        })
+    }
+
+    fn png_loader(path: &Path, file: &[u8]) -> Result<Texture, TextureError> {
+        // Palettes, 16 bit channels and grey levels below 8 bits all end up as 8 bit channels
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(|e| Self::invalid(path, &e.to_string()))?;
+
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|e| Self::invalid(path, &e.to_string()))?;
+        buffer.truncate(info.buffer_size());
+
+        let data: Vec<u8> = match info.color_type {
+            png::ColorType::Rgb            => buffer,
+            png::ColorType::Rgba           => buffer.chunks_exact(4).flat_map(|p| if p[3] < 128 {TRANSPARENT} else {[p[0], p[1], p[2]]}).collect(),
+            png::ColorType::Grayscale      => buffer.iter().flat_map(|g| [*g, *g, *g]).collect(),
+            png::ColorType::GrayscaleAlpha => buffer.chunks_exact(2).flat_map(|p| if p[1] < 128 {TRANSPARENT} else {[p[0], p[0], p[0]]}).collect(),
+            png::ColorType::Indexed        => return Err(Self::invalid(path, "palette wasn't expanded")),
+        };
+
+        Ok(Texture {width: info.width as i32, height: info.height as i32, name: String::new(), data})
+    }
+
+    // Uncompressed 24 and 32 bit BMPs, which is what every paint program saves by default
+    fn bmp_loader(path: &Path, file: &[u8]) -> Result<Texture, TextureError> {
+        if file.len() < 54 || &file[0..2] != b"BM" {return Err(Self::invalid(path, "not a BMP file"));}
+
+        let u16_at = |p: usize| u16::from_le_bytes([file[p], file[p+1]]) as usize;
+        let u32_at = |p: usize| u32::from_le_bytes([file[p], file[p+1], file[p+2], file[p+3]]);
+
+        let offset      = u32_at(10) as usize;
+        let width       = u32_at(18) as i32;
+        let height      = u32_at(22) as i32;    // Negative if rows are stored top to bottom
+        let bpp         = u16_at(28);
+        let compression = u32_at(30);
+
+        // 3 is BI_BITFIELDS, fine as long as it's standard BGRA layout
+        if (bpp != 24 && bpp != 32) || (compression != 0 && compression != 3) {
+            return Err(Self::invalid(path, &format!("only uncompressed 24 and 32 bit images are supported, this one has {} bits per pixel and compression {}", bpp, compression)));
+        }
+        if width < 1 || height == 0 {return Err(Self::invalid(path, &format!("size {}x{} is not valid", width, height)));}
+
+        let (w, h) = (width as usize, height.unsigned_abs() as usize);
+        let pixel = bpp / 8;
+        let stride = (w * pixel).div_ceil(4) * 4;   // Rows are padded to 4 bytes
+        if file.len() < offset + stride * h {return Err(Self::invalid(path, "pixel data is cut short"));}
+
+        let mut data: Vec<u8> = Vec::with_capacity(w * h * 3);
+        for row in 0..h {
+            let stored = if height > 0 {h - row - 1} else {row};
+            let start = offset + stored * stride;
+            for p in file[start .. start + w * pixel].chunks_exact(pixel) {
+                data.extend_from_slice(&[p[2], p[1], p[0]]);
+            }
+        }
+
+        Ok(Texture {width, height: h as i32, name: String::new(), data})
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Texture;
+
+    #[test]
+    fn load_order() {
+        let mut names = vec!["brick", "T10", "A", "T", "T2", "stone2", "Tile", "stone10", "T1"];
+        names.sort_by_key(|n| Texture::load_order(n));
+        assert_eq!(names, ["T1", "T2", "T10", "A", "T", "Tile", "brick", "stone2", "stone10"]);
+    }
+}
@@ -0,0 +1,32 @@
+use serde::Deserialize;
+
+use super::TextureRef;
+
+
+// Anything placed in the level that isn't a wall - pillars, lamps, pickups, enemies.
+// Drawn as a billboard, its sprite always turns to the camera.
+#[derive(Deserialize, Debug)]
+pub struct Thing {
+    pub x: f32,
+    pub y: f32,
+
+    // Sector the thing stands in, on its floor.
+    // Things outside of every sector (levels made of blocks) stand at z = 0.
+    #[serde(default)]
+    pub sector: Option<i32>,
+
+    #[serde(rename = "sprite")]
+    pub sprite_ref: TextureRef,
+    #[serde(skip)]
+    pub sprite: i32,        // Resolved `sprite_ref`
+
+    // Degrees like player's yaw, where the front of the sprite looks.
+    // Seen from behind the sprite is mirrored, like a flat cut-out would be.
+    #[serde(default)]
+    pub angle: f32,
+
+    // World units per texel of the sprite
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+fn default_scale() -> f32 {1.0}
@@ -1,5 +1,8 @@
 use serde::Deserialize;
 
+use super::TextureRef;
+use super::mover::Trigger;
+
 #[derive(Deserialize, Debug)]
 pub struct Wall {
     // Bottom points
@@ -10,11 +13,111 @@ pub struct Wall {
     pub x2: i32,
     pub y2: i32,
 
-    pub texture: i32,
+    #[serde(rename = "texture")]
+    pub texture_ref: TextureRef,
+    #[serde(skip)]
+    pub texture: i32,       // Resolved `texture_ref`
     
     // "U" and "V" denotes the axes of 2D texture (UV mapping)
     pub u: i32,
     pub v: i32,
 
-    pub shade: u8
-}
\ No newline at end of file
+    // Texels per second the textures move by along U and V (conveyor belts, waterfalls)
+    #[serde(default)]
+    pub scroll: [f32; 2],
+
+    // Makes the wall darker than its sector by `shade/2` light levels
+    pub shade: u8,
+
+    // Sector on the other side, walls with a portal are open and the renderer looks through them
+    #[serde(default)]
+    pub portal: Option<i32>,
+
+    // Using or walking over the wall starts moving sectors
+    #[serde(default)]
+    pub trigger: Option<Trigger>,
+
+    // Steps to the sector behind a portal: `lower` goes from the floor up to the floor of the next
+    // sector and `upper` from the ceiling down to its ceiling, like sidedefs in Doom. `texture` if not given.
+    #[serde(default, rename = "upper")]
+    pub upper_ref: Option<TextureRef>,
+    #[serde(skip)]
+    pub upper: i32,     // Resolved `upper_ref`
+    #[serde(default, rename = "lower")]
+    pub lower_ref: Option<TextureRef>,
+    #[serde(skip)]
+    pub lower: i32,     // Resolved `lower_ref`
+
+    // Masked texture, its `TRANSPARENT` texels (colour key or alpha) show what's behind.
+    // On a portal it fills the opening (fences, grates, windows), on a solid wall
+    // it's painted over the wall texture (banners). Repeats `u` and `v` times too.
+    #[serde(default, rename = "middle")]
+    pub middle_ref: Option<TextureRef>,
+    #[serde(skip)]
+    pub middle: Option<i32>,    // Resolved `middle_ref`
+}
+impl Wall {
+    // Point of the wall closest to (x, y)
+    pub fn closest(&self, x: f32, y: f32) -> (f32, f32) {
+        let (ax, ay) = (self.x1 as f32, self.y1 as f32);
+        let (bx, by) = (self.x2 as f32, self.y2 as f32);
+        let length = (bx-ax)*(bx-ax) + (by-ay)*(by-ay);
+        if length == 0.0 {return (ax, ay);}
+
+        let t = (((x-ax)*(bx-ax) + (y-ay)*(by-ay)) / length).clamp(0.0, 1.0);
+        (ax + t*(bx-ax), ay + t*(by-ay))
+    }
+
+    // Where the line from `from` to `to` crosses the wall, 0 at `from` and 1 at `to`
+    pub fn crossed_by(&self, from: (f32, f32), to: (f32, f32)) -> Option<f32> {
+        let (rx, ry) = (to.0 - from.0, to.1 - from.1);
+        let (sx, sy) = ((self.x2 - self.x1) as f32, (self.y2 - self.y1) as f32);
+        let cross = rx*sy - ry*sx;
+        if cross == 0.0 {return None;}     // Parallel
+
+        let (dx, dy) = (self.x1 as f32 - from.0, self.y1 as f32 - from.1);
+        let along = (dx*sy - dy*sx) / cross;
+        let on_wall = (dx*ry - dy*rx) / cross;
+        ((0.0..=1.0).contains(&along) && (0.0..=1.0).contains(&on_wall)).then_some(along)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::wall;
+
+
+    #[test]
+    fn crossed() {
+        let wall = wall(0, 0, 0, 64);
+        assert_eq!(wall.crossed_by((-8.0, 32.0), (8.0, 32.0)), Some(0.5));
+        assert_eq!(wall.crossed_by((8.0, 16.0), (-24.0, 16.0)), Some(0.25));
+
+        // Stops short of the wall or passes by its end
+        assert_eq!(wall.crossed_by((-8.0, 32.0), (-1.0, 32.0)), None);
+        assert_eq!(wall.crossed_by((-8.0, 65.0), (8.0, 65.0)), None);
+    }
+
+    #[test]
+    fn parallel() {
+        let wall = wall(0, 0, 0, 64);
+        assert_eq!(wall.crossed_by((8.0, 0.0), (8.0, 64.0)), None);
+
+        // Along the wall itself too
+        assert_eq!(wall.crossed_by((0.0, -8.0), (0.0, 72.0)), None);
+    }
+
+    #[test]
+    fn endpoints() {
+        let wall = wall(0, 0, 0, 64);
+
+        // Through the ends of the wall
+        assert_eq!(wall.crossed_by((-8.0, 64.0), (8.0, 64.0)), Some(0.5));
+        assert_eq!(wall.crossed_by((-8.0, 0.0), (8.0, 0.0)), Some(0.5));
+
+        // Line starting or ending right on the wall
+        assert_eq!(wall.crossed_by((-8.0, 32.0), (0.0, 32.0)), Some(1.0));
+        assert_eq!(wall.crossed_by((0.0, 32.0), (8.0, 32.0)), Some(0.0));
+    }
+}
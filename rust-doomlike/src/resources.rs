@@ -0,0 +1,91 @@
+// Where levels, textures and fonts come from.
+// Bottom layer is a plain directory (`src/` by default), resource packs are stacked on top of it
+// and the last pack wins, so a mod can override just the lumps it cares about.
+#[allow(dead_code)]     // Writing packs is left to the packer binary
+mod archive;
+pub use crate::resources::archive::Archive;
+
+use thiserror::Error;
+
+use std::collections::BTreeSet;
+use std::fs::{read, read_dir};
+use std::io::{self, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+
+#[derive(Debug, Error)]
+#[error("{}: {source}", path.display())]
+pub struct PackError {
+    pub path: PathBuf,
+    pub source: io::Error,
+}
+
+pub struct Resources {
+    root: PathBuf,
+    packs: Vec<(PathBuf, Archive)>,
+}
+
+impl Resources {
+    pub fn new(root: &Path, packs: &[PathBuf]) -> std::result::Result<Self, PackError> {
+        let mut resources = Resources {root: root.to_path_buf(), packs: Vec::new()};
+        for path in packs {
+            let archive = Archive::open(path).map_err(|source| PackError {path: path.clone(), source})?;
+            resources.packs.push((path.clone(), archive));
+        } Ok(resources)
+    }
+
+    // Same layers with packs read again, they could have changed on disk
+    pub fn reopen(&self) -> std::result::Result<Self, PackError> {
+        let paths: Vec<PathBuf> = self.packs.iter().map(|(p, _)| p.clone()).collect();
+        Self::new(&self.root, &paths)
+    }
+
+
+    fn name(name: &Path) -> String {
+        name.to_string_lossy().replace('\\', "/")
+    }
+
+    // Where the lump comes from, for error messages
+    pub fn describe(&self, name: &Path) -> PathBuf {
+        let lump = Self::name(name);
+        match self.packs.iter().rev().find(|(_, a)| a.get(&lump).is_some()) {
+            Some((pack, _)) => pack.join(name),
+            None => self.root.join(name),
+        }
+    }
+
+    pub fn read(&self, name: &Path) -> Result<Vec<u8>> {
+        let lump = Self::name(name);
+        for (_, archive) in self.packs.iter().rev() {
+            if let Some(data) = archive.get(&lump) {return Ok(data.to_vec());}
+        } read(self.root.join(name))
+    }
+
+    // Names of everything directly inside `dir`, from all layers
+    pub fn list(&self, dir: &Path) -> Result<Vec<String>> {
+        let mut names: BTreeSet<String> = BTreeSet::new();
+
+        let prefix = [Self::name(dir).trim_end_matches('/'), "/"].join("");
+        for (_, archive) in self.packs.iter() {
+            for lump in archive.names() {
+                if let Some(rest) = lump.strip_prefix(&prefix) {
+                    if !rest.contains('/') {names.insert(rest.to_string());}
+                }
+            }
+        }
+
+        match read_dir(self.root.join(dir)) {
+            Ok(entries) => {
+                for entry in entries.flatten() {
+                    if entry.path().is_file() {names.insert(entry.file_name().to_string_lossy().to_string());}
+                }
+            },
+            // Fine as long as some pack has the directory
+            Err(e) => if names.is_empty() {return Err(e);},
+        }
+
+        if names.is_empty() {return Err(io::Error::new(ErrorKind::NotFound, "directory is empty"));}
+        Ok(names.into_iter().collect())
+    }
+}
+
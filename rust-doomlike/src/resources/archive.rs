@@ -0,0 +1,192 @@
+// Resource pack, one file holding many named lumps, the same idea as Doom's WADs.
+//
+// Layout (all numbers little endian):
+//     "RDPK"                  magic
+//     u32                     number of lumps
+//     u32                     offset of the directory
+//     ...                     lump data
+//     directory, for every lump:
+//         u32 offset, u32 size, u16 name length, name (UTF-8)
+//
+// Names are paths with `/` separators, e.g. "levels/level.lvl" or "textures/T0.bmp".
+use std::fs::{read, write};
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+
+const MAGIC: &[u8; 4] = b"RDPK";
+const HEADER: usize = 12;
+
+pub struct Archive {
+    data: Vec<u8>,
+    lumps: Vec<(String, usize, usize)>,     // Name, offset, size
+}
+
+impl Archive {
+    // Whole file is kept in memory, packs are small
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::from_bytes(read(path)?)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self> {
+        let broken = |reason: &str| Error::new(ErrorKind::InvalidData, ["not a valid resource pack, ", reason].join(""));
+        if data.len() < HEADER || &data[0..4] != MAGIC {return Err(broken("bad header"));}
+
+        let u16_at = |p: usize| data.get(p..p+2).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize);
+        let u32_at = |p: usize| data.get(p..p+4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+
+        let count = u32_at(4).unwrap();
+        let mut p = u32_at(8).unwrap();
+
+        let mut lumps: Vec<(String, usize, usize)> = Vec::new();
+        for _ in 0..count {
+            let (Some(offset), Some(size), Some(length)) = (u32_at(p), u32_at(p+4), u16_at(p+8)) else {return Err(broken("directory is cut short"));};
+            let Some(name) = data.get(p+10 .. p+10+length) else {return Err(broken("directory is cut short"));};
+            let name = String::from_utf8(name.to_vec()).map_err(|_| broken("lump name is not UTF-8"))?;
+
+            if offset + size > data.len() {return Err(broken(&format!("lump `{}` is out of file", name)));}
+            lumps.push((name, offset, size));
+            p += 10 + length;
+        }
+
+        Ok(Archive {data, lumps})
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        // Last one wins if the same name was packed twice
+        self.lumps.iter().rev()
+            .find(|(n, _, _)| n == name)
+            .map(|(_, offset, size)| &self.data[*offset .. offset + size])
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.lumps.iter().map(|(n, _, _)| n.as_str())
+    }
+
+
+    pub fn write(path: &Path, lumps: &[(String, Vec<u8>)]) -> Result<()> {
+        write(path, Self::to_bytes(lumps)?)
+    }
+
+    // Fails if a name or the whole pack doesn't fit the sizes in the directory
+    pub fn to_bytes(lumps: &[(String, Vec<u8>)]) -> Result<Vec<u8>> {
+        let too_big = |what: &str| Error::new(ErrorKind::InvalidInput, [what, " is too big for a resource pack"].join(""));
+        let u32_of = |n: usize| u32::try_from(n).map_err(|_| too_big("pack"));
+
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&u32_of(lumps.len())?.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);    // Directory offset, filled in below
+
+        let mut offsets: Vec<usize> = Vec::with_capacity(lumps.len());
+        for (_, lump) in lumps {
+            offsets.push(data.len());
+            data.extend_from_slice(lump);
+        }
+
+        let directory = u32_of(data.len())?;
+        data[8..12].copy_from_slice(&directory.to_le_bytes());
+
+        for ((name, lump), offset) in lumps.iter().zip(offsets) {
+            let length = u16::try_from(name.len()).map_err(|_| too_big("lump name"))?;
+            data.extend_from_slice(&u32_of(offset)?.to_le_bytes());
+            data.extend_from_slice(&u32_of(lump.len())?.to_le_bytes());
+            data.extend_from_slice(&length.to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Archive;
+
+    use std::env;
+    use std::fs::remove_file;
+    use std::io::ErrorKind;
+
+
+    fn lumps() -> Vec<(String, Vec<u8>)> {
+        vec![
+            ("levels/level.lvl".to_string(), b"{}".to_vec()),
+            ("textures/T0.bmp".to_string(), vec![1, 2, 3, 4, 5]),
+            ("empty".to_string(), Vec::new()),
+            ("levels/level.lvl".to_string(), b"{\"sectors\": 0}".to_vec()),
+        ]
+    }
+
+    fn error(data: Vec<u8>) -> String {
+        let e = Archive::from_bytes(data).err().expect("broken pack was read");
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+        e.to_string()
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = env::temp_dir().join(format!("archive_round_trip_{}.pk", std::process::id()));
+        Archive::write(&path, &lumps()).unwrap();
+        let archive = Archive::open(&path);
+        remove_file(&path).unwrap();
+        let archive = archive.unwrap();
+
+        assert_eq!(archive.names().collect::<Vec<_>>(), ["levels/level.lvl", "textures/T0.bmp", "empty", "levels/level.lvl"]);
+        assert_eq!(archive.get("levels/level.lvl"), Some(&b"{\"sectors\": 0}"[..]));
+        assert_eq!(archive.get("textures/T0.bmp"), Some(&[1, 2, 3, 4, 5][..]));
+        assert_eq!(archive.get("empty"), Some(&[][..]));
+        assert_eq!(archive.get("textures/T1.bmp"), None);
+
+        let empty = Archive::from_bytes(Archive::to_bytes(&[]).unwrap()).unwrap();
+        assert_eq!(empty.names().count(), 0);
+    }
+
+    #[test]
+    fn truncated() {
+        let data = Archive::to_bytes(&lumps()).unwrap();
+        for length in 0..data.len() {
+            let e = error(data[..length].to_vec());
+            let expected = if length < 12 {"bad header"} else {"directory is cut short"};
+            assert!(e.ends_with(expected), "{} bytes: {}", length, e);
+        }
+    }
+
+    #[test]
+    fn corrupt_header() {
+        let data = Archive::to_bytes(&lumps()).unwrap();
+
+        let mut bad = data.clone();
+        bad[0..4].copy_from_slice(b"PWAD");
+        assert!(error(bad).ends_with("bad header"));
+
+        // More lumps than there are in the directory
+        let mut bad = data.clone();
+        bad[4..8].copy_from_slice(&5u32.to_le_bytes());
+        assert!(error(bad).ends_with("directory is cut short"));
+
+        let mut bad = data.clone();
+        bad[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(error(bad).ends_with("directory is cut short"));
+    }
+
+    #[test]
+    fn corrupt_directory() {
+        let data = Archive::to_bytes(&lumps()).unwrap();
+        let directory = u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize;
+
+        // Size of the first lump
+        let mut bad = data.clone();
+        bad[directory+4 .. directory+8].copy_from_slice(&1000u32.to_le_bytes());
+        assert!(error(bad).ends_with("lump `levels/level.lvl` is out of file"));
+
+        let mut bad = data.clone();
+        bad[directory+10] = 0xff;
+        assert!(error(bad).ends_with("lump name is not UTF-8"));
+    }
+    #[test]
+    fn too_big() {
+        let name = "a".repeat(u16::MAX as usize + 1);
+        let e = Archive::to_bytes(&[(name, Vec::new())]).expect_err("name that doesn't fit was packed");
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        assert!(e.to_string().ends_with("is too big for a resource pack"));
+    }
+}
@@ -0,0 +1,46 @@
+use std::fs::{metadata, read_dir};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+
+// Polls a file (or every file in a directory) for changes.
+// Cheap enough to call a few times a second, so no need for OS notifications.
+pub struct Watcher {
+    path: PathBuf,
+    stamp: Option<(SystemTime, usize)>,
+}
+
+impl Watcher {
+    pub fn new(path: &Path) -> Self {
+        Watcher {
+            path: path.to_path_buf(),
+            stamp: Self::stamp(path),
+        }
+    }
+
+    // Newest modification time and number of files,
+    // so adding or removing a file in a directory counts as a change too
+    fn stamp(path: &Path) -> Option<(SystemTime, usize)> {
+        let meta = metadata(path).ok()?;
+        if !meta.is_dir() {return Some((meta.modified().ok()?, 1));}
+
+        let mut newest = meta.modified().ok()?;
+        let mut count = 0;
+        for entry in read_dir(path).ok()?.flatten() {
+            if let Ok(modified) = entry.metadata().and_then(|m| m.modified()) {
+                if modified > newest {newest = modified;}
+            } count += 1;
+        }
+
+        Some((newest, count))
+    }
+
+    // True once per change
+    pub fn changed(&mut self) -> bool {
+        let stamp = Self::stamp(&self.path);
+        if stamp == self.stamp {return false;}
+
+        self.stamp = stamp;
+        true
+    }
+}